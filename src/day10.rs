@@ -1,94 +1,1346 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{self, BufRead, BufWriter, Write},
+    ops::{Index, IndexMut},
+    path::PathBuf,
+    str::FromStr,
+};
+
 use itertools::Itertools;
 
-aoc22::main!(day10, "../inputs/input10.txt");
+fn main() -> aoc22::MyResult<()> {
+    // a program can be stepped through with `cargo run --bin day10 -- debug [program] [crt options]`,
+    // and the puzzle input drawn on other screens with
+    // `cargo run --bin day10 -- render <ascii|blocks|half-blocks|pbm:<file>> [crt options]`
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match &args[..] {
+        [command, backend, crt_args @ ..] if command == "render" => {
+            let backend = Backend::from_name(backend)?;
+            let crt = Crt::from_args(crt_args)?;
+            let (signal_strengths, screen) = run_crt(include_str!("../inputs/input10.txt"), &crt)?;
+            println!("signal strengths: {}", signal_strengths);
+            return Ok(backend.render(&screen)?);
+        }
+        [command, rest @ ..] if command == "debug" => {
+            let (input, crt_args) = match rest {
+                [path, crt_args @ ..] if !path.starts_with("--") => {
+                    (std::fs::read_to_string(path)?, crt_args)
+                }
+                _ => (include_str!("../inputs/input10.txt").to_string(), rest),
+            };
+            return debug(&parse_instructions(&input)?, Crt::from_args(crt_args)?);
+        }
+        [] => (),
+        _ => {
+            return Err(format!(
+                "Bad arguments {:?}, expected debug [program] [crt options] or render <backend> [crt options]",
+                args
+            )
+            .into())
+        }
+    }
+    let (part1, part2) = day10(include_str!("../inputs/input10.txt"))?;
+    println!("part1: {}", part1);
+    match aoc22::ocr::recognise(&part2) {
+        Ok(letters) => println!("part2: {}", letters),
+        Err(e) => println!("part2: {}\n{}", e, part2),
+    }
+    Ok(())
+}
 
 #[cfg(test)]
 const EXAMPLE2_EXPECTED: &str = "##..##..##..##..##..##..##..##..##..##..\n###...###...###...###...###...###...###.\n####....####....####....####....####....\n#####.....#####.....#####.....#####.....\n######......######......######......####\n#######.......#######.......#######.....\n";
 aoc22::test_with_example!(day10, "../inputs/example10.txt", 13140, EXAMPLE2_EXPECTED);
 
-#[derive(Debug, Default, Copy, Clone)]
-enum Instruction {
-    #[default]
-    NoOp,
-    AddX(i32),
+const REGISTER_NAMES: [&str; 4] = ["x", "y", "z", "w"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Register(usize);
+
+const X: Register = Register(0);
+
+impl FromStr for Register {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        REGISTER_NAMES
+            .iter()
+            .position(|&name| name == s)
+            .map(Register)
+            .ok_or(())
+    }
+}
+
+impl Display for Register {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", REGISTER_NAMES[self.0])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Registers([i32; REGISTER_NAMES.len()]);
+
+impl Registers {
+    /// x starts at 1, the other registers at 0
+    fn initial() -> Registers {
+        let mut registers = Registers([0; REGISTER_NAMES.len()]);
+        registers[X] = 1;
+        registers
+    }
+}
+
+impl Index<Register> for Registers {
+    type Output = i32;
+
+    fn index(&self, Register(r): Register) -> &i32 {
+        &self.0[r]
+    }
+}
+
+impl IndexMut<Register> for Registers {
+    fn index_mut(&mut self, Register(r): Register) -> &mut i32 {
+        &mut self.0[r]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OperandKind {
+    Register,
+    Immediate,
+    /// a register or an immediate value
+    Source,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Register(Register),
+    Immediate(i32),
+}
+
+impl Operand {
+    fn parse(s: &str, kind: OperandKind) -> Option<Operand> {
+        let register = || s.parse().ok().map(Operand::Register);
+        let immediate = || s.parse().ok().map(Operand::Immediate);
+        match kind {
+            OperandKind::Register => register(),
+            OperandKind::Immediate => immediate(),
+            OperandKind::Source => register().or_else(immediate),
+        }
+    }
+
+    fn value(&self, registers: &Registers) -> i32 {
+        match *self {
+            Operand::Register(r) => registers[r],
+            Operand::Immediate(v) => v,
+        }
+    }
+
+    /// The register written by an instruction, checked when parsing
+    fn register(&self) -> Register {
+        match *self {
+            Operand::Register(r) => r,
+            Operand::Immediate(_) => unreachable!("operand kinds are checked when parsing"),
+        }
+    }
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operand::Register(r) => write!(f, "{}", r),
+            Operand::Immediate(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// An entry of the instruction set
+#[derive(Debug)]
+struct Opcode {
+    mnemonic: &'static str,
+    operands: &'static [OperandKind],
+    /// number of cycles the instruction takes, its effect being applied at the end of the last one
+    cycles: usize,
+    /// None if the result doesn't fit in a register
+    effect: fn(&mut Registers, &[Operand]) -> Option<()>,
+}
+
+const INSTRUCTION_SET: &[Opcode] = &[
+    Opcode {
+        mnemonic: "noop",
+        operands: &[],
+        cycles: 1,
+        effect: |_, _| Some(()),
+    },
+    Opcode {
+        mnemonic: "addx",
+        operands: &[OperandKind::Immediate],
+        cycles: 2,
+        effect: |registers, operands| {
+            registers[X] = registers[X].checked_add(operands[0].value(registers))?;
+            Some(())
+        },
+    },
+    Opcode {
+        mnemonic: "add",
+        operands: &[OperandKind::Register, OperandKind::Source],
+        cycles: 2,
+        effect: |registers, operands| {
+            let target = operands[0].register();
+            registers[target] = registers[target].checked_add(operands[1].value(registers))?;
+            Some(())
+        },
+    },
+    Opcode {
+        mnemonic: "mul",
+        operands: &[OperandKind::Register, OperandKind::Source],
+        cycles: 3,
+        effect: |registers, operands| {
+            let target = operands[0].register();
+            registers[target] = registers[target].checked_mul(operands[1].value(registers))?;
+            Some(())
+        },
+    },
+    Opcode {
+        mnemonic: "set",
+        operands: &[OperandKind::Register, OperandKind::Source],
+        cycles: 1,
+        effect: |registers, operands| {
+            registers[operands[0].register()] = operands[1].value(registers);
+            Some(())
+        },
+    },
+];
+
+#[derive(Debug, Clone)]
+struct Instruction {
+    opcode: &'static Opcode,
+    operands: Vec<Operand>,
 }
 
 impl Instruction {
-    fn apply(&self, x: i32) -> i32 {
+    fn apply(&self, registers: &mut Registers) -> Option<()> {
+        (self.opcode.effect)(registers, &self.operands)
+    }
+    fn cycle_count(&self) -> usize {
+        self.opcode.cycles
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.opcode.mnemonic)?;
+        for operand in &self.operands {
+            write!(f, " {}", operand)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ProgramError {
+    /// 1-based line number of an instruction that isn't in the instruction set
+    UnknownOpcode {
+        line: usize,
+        mnemonic: String,
+    },
+    WrongOperandCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    BadOperand {
+        line: usize,
+        operand: String,
+    },
+}
+
+impl Display for ProgramError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::NoOp => x,
-            Self::AddX(v) => x + v,
+            Self::UnknownOpcode { line, mnemonic } => {
+                write!(f, "line {}: unknown opcode {:?}", line, mnemonic)
+            }
+            Self::WrongOperandCount {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: expected {} operands, found {}",
+                line, expected, found
+            ),
+            Self::BadOperand { line, operand } => {
+                write!(f, "line {}: bad operand {:?}", line, operand)
+            }
         }
     }
-    fn cycle_count(&self) -> i32 {
+}
+
+impl Error for ProgramError {}
+
+#[derive(Debug, PartialEq)]
+enum ExecutionError {
+    /// The instruction ending during this cycle wrote a value that doesn't fit in a register
+    Overflow { cycle: usize, instruction: String },
+}
+
+impl Display for ExecutionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::NoOp => 1,
-            Self::AddX(_) => 2,
+            Self::Overflow { cycle, instruction } => {
+                write!(f, "cycle {}: overflow in `{}`", cycle, instruction)
+            }
         }
     }
 }
 
+impl Error for ExecutionError {}
+
+/// Runs a program one cycle at a time, yielding the registers during each cycle.
+/// It stops after an instruction fails.
+struct Cpu<'a> {
+    program: &'a [Instruction],
+    registers: Registers,
+    /// index of the instruction being executed
+    pc: usize,
+    /// cycles already spent on the current instruction
+    elapsed: usize,
+    cycle: usize,
+    failed: bool,
+}
+
+impl<'a> Cpu<'a> {
+    fn new(program: &'a [Instruction]) -> Cpu<'a> {
+        Cpu {
+            program,
+            registers: Registers::initial(),
+            pc: 0,
+            elapsed: 0,
+            cycle: 0,
+            failed: false,
+        }
+    }
+}
+
+impl Iterator for Cpu<'_> {
+    type Item = Result<Registers, ExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let instruction = self.program.get(self.pc)?;
+        let during = self.registers;
+        self.cycle += 1;
+        self.elapsed += 1;
+        if self.elapsed == instruction.cycle_count() {
+            if instruction.apply(&mut self.registers).is_none() {
+                self.failed = true;
+                return Some(Err(ExecutionError::Overflow {
+                    cycle: self.cycle,
+                    instruction: instruction.to_string(),
+                }));
+            }
+            self.pc += 1;
+            self.elapsed = 0;
+        }
+        Some(Ok(during))
+    }
+}
+
 pub fn day10(input: &str) -> aoc22::MyResult<(i32, String)> {
-    let instructions = parse_instructions(input);
+    let (part1, screen) = run_crt(input, &Crt::default())?;
+    Ok((part1, screen.to_ascii()))
+}
+
+/// Sum of the signal strengths sampled during the program, and the screen it draws
+fn run_crt(input: &str, crt: &Crt) -> aoc22::MyResult<(i32, Screen)> {
+    let instructions = parse_instructions(input)?;
 
     let mut states: Vec<i32> = Vec::new();
-    let mut x = 1;
-    states.push(x); // cycle 0
-    for instr in instructions {
-        for _ in 0..instr.cycle_count() {
-            states.push(x)
-        }
-        x = instr.apply(x);
+    states.push(Registers::initial()[X]); // cycle 0
+    for registers in Cpu::new(&instructions) {
+        states.push(registers?[X]);
     }
 
-    let part1 = states
+    let signal_strengths = states
         .iter()
         .enumerate()
-        .filter_map(|(cycle, x)| {
-            if is_interesting(cycle) {
-                Some((cycle as i32) * x)
-            } else {
-                None
-            }
+        .filter(|(cycle, _)| crt.sampling_cycles.contains(cycle))
+        .try_fold(0i32, |sum, (cycle, &x)| {
+            sum.checked_add(i32::try_from(cycle).ok()?.checked_mul(x)?)
         })
-        .sum();
+        .ok_or("Signal strength overflow")?;
 
-    let part2 = render_screen(&states);
-
-    Ok((part1, part2))
+    Ok((signal_strengths, crt.draw(&states)?))
 }
 
-fn parse_instructions(input: &str) -> Vec<Instruction> {
+fn parse_instructions(input: &str) -> Result<Vec<Instruction>, ProgramError> {
     input
         .lines()
-        .map(|l| match l.split_once(' ') {
-            Some(("addx", val)) => Instruction::AddX(val.parse().unwrap()),
-            _ => Instruction::NoOp,
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(n, l)| {
+            let line = n + 1;
+            let mut words = l.split_whitespace();
+            let mnemonic = words.next().unwrap_or_default();
+            let args: Vec<&str> = words.collect();
+            let opcode = INSTRUCTION_SET
+                .iter()
+                .find(|op| op.mnemonic == mnemonic)
+                .ok_or_else(|| ProgramError::UnknownOpcode {
+                    line,
+                    mnemonic: mnemonic.to_string(),
+                })?;
+            if args.len() != opcode.operands.len() {
+                return Err(ProgramError::WrongOperandCount {
+                    line,
+                    expected: opcode.operands.len(),
+                    found: args.len(),
+                });
+            }
+            let operands = args
+                .iter()
+                .zip(opcode.operands)
+                .map(|(arg, &kind)| {
+                    Operand::parse(arg, kind).ok_or_else(|| ProgramError::BadOperand {
+                        line,
+                        operand: arg.to_string(),
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(Instruction { opcode, operands })
         })
         .collect()
 }
 
 const INTERESTING_CYCLES: [usize; 6] = [20, 60, 100, 140, 180, 220];
 
-fn is_interesting(cycle: usize) -> bool {
-    INTERESTING_CYCLES.contains(&cycle)
+/// Geometry of the CRT, and when the signal strength is sampled
+#[derive(Debug, Clone, PartialEq)]
+struct Crt {
+    width: usize,
+    height: usize,
+    /// pixels covered by the sprite, centred on x (the extra pixel of even widths being on the right)
+    sprite_width: usize,
+    sampling_cycles: Vec<usize>,
 }
 
-fn render_screen(states: &[i32]) -> String {
-    states
-        .iter()
-        .enumerate()
-        .skip(1)
-        .map(|(cycle, x)| {
-            let pos = ((cycle - 1) % 40) as i32;
-            if (pos - x).abs() <= 1 {
-                '#'
+impl Default for Crt {
+    fn default() -> Self {
+        Crt {
+            width: 40,
+            height: 6,
+            sprite_width: 3,
+            sampling_cycles: INTERESTING_CYCLES.to_vec(),
+        }
+    }
+}
+
+impl Crt {
+    /// `[--width <n>] [--height <n>] [--sprite <n>] [--sample <cycle>,<cycle>,...]`
+    fn from_args(args: &[String]) -> aoc22::MyResult<Crt> {
+        let mut crt = Crt::default();
+        for option in args.chunks(2) {
+            match option {
+                [flag, n] if flag == "--width" => crt.width = n.parse()?,
+                [flag, n] if flag == "--height" => crt.height = n.parse()?,
+                [flag, n] if flag == "--sprite" => crt.sprite_width = n.parse()?,
+                [flag, cycles] if flag == "--sample" => {
+                    crt.sampling_cycles = cycles
+                        .split(',')
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()?
+                }
+                _ => return Err(format!("Bad CRT option {:?}", option).into()),
+            }
+        }
+        crt.pixel_count()?;
+        Ok(crt)
+    }
+
+    fn pixel_count(&self) -> aoc22::MyResult<usize> {
+        if self.width == 0 {
+            return Err("The CRT needs at least one column".into());
+        }
+        Ok(self
+            .width
+            .checked_mul(self.height)
+            .ok_or_else(|| format!("A {}x{} CRT is too big", self.width, self.height))?)
+    }
+
+    /// The pixels drawn during each cycle, given the value of x during each cycle (from cycle 0).
+    /// Cycles past the end of the screen are ignored.
+    fn draw(&self, states: &[i32]) -> aoc22::MyResult<Screen> {
+        // in 128 bits, nothing overflows whatever the sprite width and x
+        let left = (self.sprite_width as i128 - 1).div_euclid(2);
+        let right = self.sprite_width as i128 / 2;
+        let pixels = states
+            .iter()
+            .skip(1)
+            .take(self.pixel_count()?)
+            .enumerate()
+            .map(|(n, &x)| {
+                let (pos, x) = ((n % self.width) as i128, x as i128);
+                self.sprite_width > 0 && x - left <= pos && pos <= x + right
+            })
+            .collect();
+        Ok(Screen {
+            width: self.width,
+            height: self.height,
+            pixels,
+        })
+    }
+}
+
+/// Pixels of a CRT, row by row; the last rows are missing if the program stopped early
+#[derive(Debug, Clone, PartialEq)]
+struct Screen {
+    width: usize,
+    height: usize,
+    pixels: Vec<bool>,
+}
+
+/// Ways to show a screen
+#[derive(Debug, Clone, PartialEq)]
+enum Backend {
+    /// `#` and `.`, like in the puzzle
+    Ascii,
+    /// a full block per pixel
+    Blocks,
+    /// two rows of pixels per line of text
+    HalfBlocks,
+    /// a PBM image file
+    Pbm(PathBuf),
+}
+
+impl Backend {
+    fn from_name(name: &str) -> aoc22::MyResult<Backend> {
+        match name.split_once(':') {
+            _ if name == "ascii" => Ok(Backend::Ascii),
+            _ if name == "blocks" => Ok(Backend::Blocks),
+            _ if name == "half-blocks" => Ok(Backend::HalfBlocks),
+            Some(("pbm", path)) => Ok(Backend::Pbm(PathBuf::from(path))),
+            _ => Err(format!(
+                "Unknown backend {:?}, expected ascii, blocks, half-blocks or pbm:<file>",
+                name
+            )
+            .into()),
+        }
+    }
+
+    fn render(&self, screen: &Screen) -> io::Result<()> {
+        match self {
+            Backend::Ascii => print!("{}", screen.to_ascii()),
+            Backend::Blocks => print!("{}", screen.to_blocks()),
+            Backend::HalfBlocks => print!("{}", screen.to_half_blocks()),
+            Backend::Pbm(path) => screen.write_pbm(BufWriter::new(File::create(path)?))?,
+        }
+        Ok(())
+    }
+}
+
+impl Screen {
+    fn rows(&self) -> impl Iterator<Item = &[bool]> {
+        self.pixels.chunks(self.width)
+    }
+
+    fn to_text(&self, lit: char, unlit: char) -> String {
+        self.rows()
+            .map(|row| {
+                row.iter()
+                    .map(|&p| if p { lit } else { unlit })
+                    .chain(std::iter::once('\n'))
+                    .collect::<String>()
+            })
+            .collect()
+    }
+
+    fn to_ascii(&self) -> String {
+        self.to_text('#', '.')
+    }
+
+    fn to_blocks(&self) -> String {
+        self.to_text('█', ' ')
+    }
+
+    fn to_half_blocks(&self) -> String {
+        let rows: Vec<&[bool]> = self.rows().collect();
+        rows.chunks(2)
+            .map(|pair| {
+                let (top, bottom) = (pair[0], pair.get(1).copied().unwrap_or_default());
+                top.iter()
+                    .enumerate()
+                    .map(
+                        |(i, &t)| match (t, bottom.get(i).copied().unwrap_or(false)) {
+                            (true, true) => '█',
+                            (true, false) => '▀',
+                            (false, true) => '▄',
+                            (false, false) => ' ',
+                        },
+                    )
+                    .chain(std::iter::once('\n'))
+                    .collect::<String>()
+            })
+            .collect()
+    }
+
+    /// Write the whole screen as a plain PBM image, with the pixels not drawn yet left blank
+    fn write_pbm(&self, mut out: impl Write) -> io::Result<()> {
+        writeln!(out, "P1\n{} {}", self.width, self.height)?;
+        for row in 0..self.height {
+            let line = (0..self.width)
+                .map(|col| match self.pixels.get(row * self.width + col) {
+                    Some(true) => "1",
+                    _ => "0",
+                })
+                .join(" ");
+            writeln!(out, "{}", line)?;
+        }
+        out.flush()
+    }
+}
+
+/// A value of a debugging expression
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Term {
+    Value(i32),
+    Register(Register),
+    Cycle,
+    /// the cycle number times x
+    Signal,
+}
+
+/// Expressions used by the debugger, e.g. `signal - 2 * x`: sums of products of terms
+#[derive(Debug, Clone, PartialEq)]
+struct Expr {
+    /// products, each with its sign
+    products: Vec<(i32, Vec<Term>)>,
+    text: String,
+}
+
+impl FromStr for Expr {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = Vec::new();
+        let mut chars = s.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c.is_ascii_alphanumeric() {
+                let mut word = String::new();
+                while let Some(c) = chars.next_if(char::is_ascii_alphanumeric) {
+                    word.push(c);
+                }
+                tokens.push(word);
             } else {
-                '.'
+                tokens.push(chars.next().unwrap().to_string());
             }
+        }
+
+        let mut products = Vec::new();
+        let mut sign = 1;
+        let mut product = Vec::new();
+        let mut expect_term = true;
+        for token in tokens {
+            match (expect_term, token.as_str()) {
+                // a minus on any term negates the whole product
+                (true, "-") => sign = -sign,
+                (true, word) => {
+                    product.push(match word {
+                        "cycle" => Term::Cycle,
+                        "signal" => Term::Signal,
+                        _ => match word.parse() {
+                            Ok(r) => Term::Register(r),
+                            Err(()) => Term::Value(
+                                word.parse()
+                                    .map_err(|_| format!("Unknown term {:?} in {:?}", word, s))?,
+                            ),
+                        },
+                    });
+                    expect_term = false;
+                }
+                (false, "*") => expect_term = true,
+                (false, "+" | "-") => {
+                    products.push((sign, std::mem::take(&mut product)));
+                    sign = if token == "-" { -1 } else { 1 };
+                    expect_term = true;
+                }
+                (false, other) => return Err(format!("Unexpected {:?} in {:?}", other, s).into()),
+            }
+        }
+        if expect_term {
+            return Err(format!("Incomplete expression {:?}", s).into());
+        }
+        products.push((sign, product));
+        Ok(Expr {
+            products,
+            text: s.trim().to_string(),
+        })
+    }
+}
+
+/// The value of an expression doesn't fit in 32 bits
+#[derive(Debug, PartialEq)]
+struct EvalOverflow {
+    expr: String,
+}
+
+impl Display for EvalOverflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "overflow in `{}`", self.expr)
+    }
+}
+
+impl Error for EvalOverflow {}
+
+impl Expr {
+    /// Value of the expression during a cycle
+    fn eval(&self, cycle: usize, registers: &Registers) -> Result<i32, EvalOverflow> {
+        let term = |term: &Term| match *term {
+            Term::Value(v) => Some(v),
+            Term::Register(r) => Some(registers[r]),
+            Term::Cycle => i32::try_from(cycle).ok(),
+            Term::Signal => i32::try_from(cycle).ok()?.checked_mul(registers[X]),
+        };
+        self.products
+            .iter()
+            .try_fold(0i32, |sum, (sign, product)| {
+                let product = product
+                    .iter()
+                    .try_fold(*sign, |product, t| product.checked_mul(term(t)?))?;
+                sum.checked_add(product)
+            })
+            .ok_or_else(|| EvalOverflow {
+                expr: self.text.clone(),
+            })
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Le,
+    Ge,
+    Lt,
+    Gt,
+}
+
+const COMPARISONS: [(&str, Comparison); 6] = [
+    ("==", Comparison::Eq),
+    ("!=", Comparison::Ne),
+    ("<=", Comparison::Le),
+    (">=", Comparison::Ge),
+    ("<", Comparison::Lt),
+    (">", Comparison::Gt),
+];
+
+/// A breakpoint: `<expr> <comparison> <expr>`, or a cycle number
+#[derive(Debug, Clone, PartialEq)]
+struct Condition {
+    left: Expr,
+    comparison: Comparison,
+    right: Expr,
+}
+
+impl FromStr for Condition {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(cycle) = s.trim().parse::<usize>() {
+            return Ok(Condition {
+                left: "cycle".parse()?,
+                comparison: Comparison::Eq,
+                right: cycle.to_string().parse()?,
+            });
+        }
+        let (symbol, comparison) = COMPARISONS
+            .iter()
+            .find(|(symbol, _)| s.contains(symbol))
+            .ok_or_else(|| format!("No comparison in {:?}", s))?;
+        let (left, right) = s.split_once(symbol).unwrap();
+        Ok(Condition {
+            left: left.parse()?,
+            comparison: *comparison,
+            right: right.parse()?,
         })
-        .chunks(40)
-        .into_iter()
-        .map(|a| a.collect::<String>() + "\n")
-        .fold(String::new(), |acc, elem| acc + &elem)
+    }
+}
+
+impl Condition {
+    fn holds(&self, cycle: usize, registers: &Registers) -> Result<bool, EvalOverflow> {
+        let (left, right) = (
+            self.left.eval(cycle, registers)?,
+            self.right.eval(cycle, registers)?,
+        );
+        Ok(match self.comparison {
+            Comparison::Eq => left == right,
+            Comparison::Ne => left != right,
+            Comparison::Le => left <= right,
+            Comparison::Ge => left >= right,
+            Comparison::Lt => left < right,
+            Comparison::Gt => left > right,
+        })
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (symbol, _) = COMPARISONS
+            .iter()
+            .find(|(_, c)| *c == self.comparison)
+            .unwrap();
+        write!(f, "{} {} {}", self.left, symbol, self.right)
+    }
+}
+
+/// The registers during a cycle, and the instruction being executed
+#[derive(Debug, Clone, Copy)]
+struct Snapshot {
+    registers: Registers,
+    /// None for cycle 0, before the program starts
+    instruction: Option<usize>,
+}
+
+/// Runs a program cycle by cycle, stopping on breakpoints
+struct Debugger<'a> {
+    cpu: Cpu<'a>,
+    /// the screen drawn by the program
+    crt: Crt,
+    /// every cycle so far, starting with cycle 0 like the states of `day10`
+    history: Vec<Snapshot>,
+    breakpoints: Vec<Condition>,
+    watches: Vec<Expr>,
+}
+
+const DEBUGGER_HELP: &str = "\
+commands:
+  [enter] | s [n]      step one or n cycles
+  c                    continue until a breakpoint or the end of the program
+  b [<cycle>|<cond>]   list breakpoints, or break on a cycle or a condition like `x > 10`
+  d <n>                delete breakpoint n
+  w <expr>             watch an expression like `signal` or `x * 40 - cycle`
+  p <expr>             print an expression
+  signal [cycle]       signal strength during the current or a past cycle
+  crt                  show the screen drawn so far
+  q                    quit";
+
+impl<'a> Debugger<'a> {
+    fn new(program: &'a [Instruction], crt: Crt) -> Debugger<'a> {
+        Debugger {
+            cpu: Cpu::new(program),
+            crt,
+            history: vec![Snapshot {
+                registers: Registers::initial(),
+                instruction: None,
+            }],
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+        }
+    }
+
+    fn cycle(&self) -> usize {
+        self.history.len() - 1
+    }
+
+    fn registers(&self) -> &Registers {
+        &self.history[self.cycle()].registers
+    }
+
+    /// Run one cycle, returning false if the program is over
+    fn step(&mut self) -> Result<bool, ExecutionError> {
+        let instruction = self.cpu.pc;
+        match self.cpu.next().transpose()? {
+            Some(registers) => {
+                self.history.push(Snapshot {
+                    registers,
+                    instruction: Some(instruction),
+                });
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Run until a breakpoint holds, returning it, or until the end of the program
+    fn resume(&mut self) -> aoc22::MyResult<Option<&Condition>> {
+        while self.step()? {
+            let (cycle, registers) = (self.cycle(), *self.registers());
+            let mut hit = None;
+            for (n, breakpoint) in self.breakpoints.iter().enumerate() {
+                if breakpoint.holds(cycle, &registers)? {
+                    hit = Some(n);
+                    break;
+                }
+            }
+            if let Some(hit) = hit {
+                return Ok(Some(&self.breakpoints[hit]));
+            }
+        }
+        Ok(None)
+    }
+
+    fn signal_strength(&self, cycle: usize) -> aoc22::MyResult<i32> {
+        let snapshot = self
+            .history
+            .get(cycle)
+            .ok_or_else(|| format!("Cycle {} not reached yet", cycle))?;
+        Ok(Expr::from_str("signal")?.eval(cycle, &snapshot.registers)?)
+    }
+
+    /// The pixels drawn up to the current cycle
+    fn screen(&self) -> aoc22::MyResult<String> {
+        let states: Vec<i32> = self.history.iter().map(|s| s.registers[X]).collect();
+        Ok(self.crt.draw(&states)?.to_ascii())
+    }
+
+    /// Current cycle, instruction, registers and watches
+    fn status(&self) -> String {
+        let snapshot = &self.history[self.cycle()];
+        let instruction = match snapshot.instruction {
+            Some(pc) => format!("{}: {}", pc + 1, self.cpu.program[pc]),
+            None => "not started".to_string(),
+        };
+        let registers = REGISTER_NAMES
+            .iter()
+            .zip(snapshot.registers.0)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .join(" ");
+        let mut status = format!("cycle {} [{}] {}", self.cycle(), instruction, registers);
+        for watch in &self.watches {
+            status += &format!(
+                "\n  {} = {}",
+                watch,
+                match watch.eval(self.cycle(), &snapshot.registers) {
+                    Ok(value) => value.to_string(),
+                    Err(e) => e.to_string(),
+                }
+            );
+        }
+        status
+    }
+
+    /// Run a command, returning what to show, or None to quit
+    fn execute(&mut self, command: &str) -> aoc22::MyResult<Option<String>> {
+        let (name, args) = command
+            .trim()
+            .split_once(' ')
+            .unwrap_or((command.trim(), ""));
+        let args = args.trim();
+        let output = match name {
+            "" | "s" => {
+                let count = if args.is_empty() { 1 } else { args.parse()? };
+                let mut ended = false;
+                for _ in 0..count {
+                    if !self.step()? {
+                        ended = true;
+                        break;
+                    }
+                }
+                self.stopped(ended.then_some("program ended".to_string()))?
+            }
+            "c" => {
+                let reason = match self.resume()? {
+                    Some(breakpoint) => format!("breakpoint: {}", breakpoint),
+                    None => "program ended".to_string(),
+                };
+                self.stopped(Some(reason))?
+            }
+            "b" if args.is_empty() => self
+                .breakpoints
+                .iter()
+                .enumerate()
+                .map(|(n, b)| format!("{}: {}", n + 1, b))
+                .join("\n"),
+            "b" => {
+                self.breakpoints.push(args.parse()?);
+                format!("breakpoint {}: {}", self.breakpoints.len(), args)
+            }
+            "d" => {
+                let n: usize = args.parse()?;
+                if n == 0 || n > self.breakpoints.len() {
+                    return Err(format!("No breakpoint {}", n).into());
+                }
+                format!("deleted {}", self.breakpoints.remove(n - 1))
+            }
+            "w" => {
+                self.watches.push(args.parse()?);
+                self.status()
+            }
+            "p" => {
+                let expr: Expr = args.parse()?;
+                expr.eval(self.cycle(), self.registers())?.to_string()
+            }
+            "signal" => {
+                let cycle = if args.is_empty() {
+                    self.cycle()
+                } else {
+                    args.parse()?
+                };
+                let strength = self.signal_strength(cycle)?;
+                format!("signal strength during cycle {}: {}", cycle, strength)
+            }
+            "crt" => self.screen()?,
+            "q" => return Ok(None),
+            "h" | "help" => DEBUGGER_HELP.to_string(),
+            _ => return Err(format!("Unknown command {:?}, h for help", command).into()),
+        };
+        Ok(Some(output))
+    }
+
+    /// What to show after running the program: the screen so far, then the status
+    fn stopped(&self, reason: Option<String>) -> aoc22::MyResult<String> {
+        let reason = reason.map(|r| r + "\n").unwrap_or_default();
+        Ok(format!("{}{}{}", self.screen()?, reason, self.status()))
+    }
+}
+
+fn debug(program: &[Instruction], crt: Crt) -> aoc22::MyResult<()> {
+    let mut debugger = Debugger::new(program, crt);
+    let mut stdout = std::io::stdout();
+    let mut input = std::io::stdin().lock().lines();
+    println!("{}\n{}", DEBUGGER_HELP, debugger.status());
+    loop {
+        write!(stdout, "> ")?;
+        stdout.flush()?;
+        let Some(command) = input.next().transpose()? else {
+            return Ok(());
+        };
+        match debugger.execute(&command) {
+            Ok(Some(output)) => println!("{}", output),
+            Ok(None) => return Ok(()),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod cpu_tests {
+    use super::*;
+
+    fn run(program: &str) -> aoc22::MyResult<Vec<Registers>> {
+        Ok(Cpu::new(&parse_instructions(program)?).collect::<Result<_, _>>()?)
+    }
+
+    #[test]
+    fn cycle_accurate_registers() -> aoc22::MyResult<()> {
+        let y = "y".parse().unwrap();
+        let trace = run("set y 3\nmul y 2\nadd x y\naddx -2\nnoop")?;
+        // set, mul, add and addx take 1, 3, 2 and 2 cycles
+        assert_eq!(trace.len(), 9);
+        let xs: Vec<i32> = trace.iter().map(|r| r[X]).collect();
+        let ys: Vec<i32> = trace.iter().map(|r| r[y]).collect();
+        assert_eq!(xs, [1, 1, 1, 1, 1, 1, 7, 7, 5]);
+        assert_eq!(ys, [0, 3, 3, 3, 6, 6, 6, 6, 6]);
+        Ok(())
+    }
+
+    #[test]
+    fn program_errors() {
+        assert_eq!(
+            parse_instructions("noop\nadx 3").err(),
+            Some(ProgramError::UnknownOpcode {
+                line: 2,
+                mnemonic: "adx".to_string()
+            })
+        );
+        assert_eq!(
+            parse_instructions("noop\n\naddx").err(),
+            Some(ProgramError::WrongOperandCount {
+                line: 3,
+                expected: 1,
+                found: 0
+            })
+        );
+        assert_eq!(
+            parse_instructions("addx y").err(),
+            Some(ProgramError::BadOperand {
+                line: 1,
+                operand: "y".to_string()
+            })
+        );
+        assert_eq!(
+            parse_instructions("set 3 x").err(),
+            Some(ProgramError::BadOperand {
+                line: 1,
+                operand: "3".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn overflows() -> Result<(), ProgramError> {
+        let program = parse_instructions("set x 2147483647\nnoop\naddx 1\nnoop")?;
+        let trace: Vec<_> = Cpu::new(&program).collect();
+        assert_eq!(trace.len(), 4);
+        assert_eq!(trace[2].as_ref().map(|r| r[X]), Ok(i32::MAX));
+        assert_eq!(
+            trace[3],
+            Err(ExecutionError::Overflow {
+                cycle: 4,
+                instruction: "addx 1".to_string()
+            })
+        );
+        let program = parse_instructions("set y -65536\nmul y y")?;
+        assert!(Cpu::new(&program).any(|registers| registers.is_err()));
+        // the largest values still work
+        let program = parse_instructions("set x -2147483647\naddx -1\nset y 1\nmul y x")?;
+        assert!(Cpu::new(&program).all(|registers| registers.is_ok()));
+        Ok(())
+    }
+
+    #[test]
+    fn letters_on_the_screen() -> aoc22::MyResult<()> {
+        let (_, screen) = day10(include_str!("../inputs/input10.txt"))?;
+        assert_eq!(aoc22::ocr::recognise(&screen)?, "PLGFKAZG");
+        Ok(())
+    }
+
+    #[test]
+    fn display_instructions() -> Result<(), ProgramError> {
+        let program = "noop\naddx -5\nmul z w\nset y 7";
+        let instructions = parse_instructions(program)?;
+        assert_eq!(instructions.iter().join("\n"), program);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod debugger_tests {
+    use super::*;
+
+    fn example() -> Vec<Instruction> {
+        parse_instructions(include_str!("../inputs/example10.txt")).unwrap()
+    }
+
+    #[test]
+    fn expressions() -> aoc22::MyResult<()> {
+        let mut registers = Registers::initial();
+        registers[X] = 3;
+        registers["y".parse().unwrap()] = -2;
+        let eval =
+            |s: &str| -> aoc22::MyResult<i32> { Ok(s.parse::<Expr>()?.eval(20, &registers)?) };
+        assert_eq!(eval("signal")?, 60);
+        assert_eq!(eval("x * 40 - cycle")?, 100);
+        assert_eq!(eval("-y + 2 * x * y")?, -10);
+        assert_eq!(eval("x * -2")?, -6);
+        assert_eq!(eval("x * -y * -2 - -1")?, -11);
+        assert_eq!(eval("-x * -y")?, -6);
+        assert!(eval("x * -").is_err());
+        assert!(eval("x +").is_err());
+        assert!(eval("x y").is_err());
+        assert!(eval("foo").is_err());
+
+        let condition: Condition = "x >= 3".parse()?;
+        assert!(condition.holds(1, &registers)?);
+        assert_eq!(condition.to_string(), "x >= 3");
+        assert!("20".parse::<Condition>()?.holds(20, &registers)?);
+        assert!("x".parse::<Condition>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn overflowing_expressions() -> aoc22::MyResult<()> {
+        let registers = Registers::initial();
+        let expr: Expr = "x * 100000 * 100000".parse()?;
+        assert_eq!(
+            expr.eval(1, &registers),
+            Err(EvalOverflow {
+                expr: "x * 100000 * 100000".to_string()
+            })
+        );
+        assert!("2147483647 + x"
+            .parse::<Expr>()?
+            .eval(1, &registers)
+            .is_err());
+        assert_eq!(
+            "-2147483647 - x".parse::<Expr>()?.eval(1, &registers),
+            Ok(i32::MIN)
+        );
+        assert!("cycle"
+            .parse::<Expr>()?
+            .eval(usize::MAX, &registers)
+            .is_err());
+        assert!("x * 100000 * 100000 > 0"
+            .parse::<Condition>()?
+            .holds(1, &registers)
+            .is_err());
+
+        let program = parse_instructions("set x 1000000\nnoop\nnoop\nnoop")?;
+        let mut debugger = Debugger::new(&program, Crt::default());
+        assert!(debugger.execute("p x * 100000 * 100000").is_err());
+        debugger.execute("w x * x")?;
+        debugger.execute("b x * x < 0")?;
+        assert!(debugger.execute("c").is_err());
+        // the session goes on
+        assert_eq!(debugger.cycle(), 2);
+        let output = debugger.execute("s")?.unwrap();
+        assert!(output.ends_with("  x * x = overflow in `x * x`"));
+        Ok(())
+    }
+
+    #[test]
+    fn breakpoints_and_signal() -> aoc22::MyResult<()> {
+        let program = example();
+        let mut debugger = Debugger::new(&program, Crt::default());
+        for cycle in [20, 60] {
+            debugger.execute(&format!("b {}", cycle))?;
+        }
+        debugger.execute("w signal")?;
+        let output = debugger.execute("c")?.unwrap();
+        assert_eq!(debugger.cycle(), 20);
+        assert!(output.contains("breakpoint: cycle == 20"));
+        assert!(output.ends_with("  signal = 420"));
+        debugger.execute("c")?;
+        assert_eq!(debugger.cycle(), 60);
+        assert_eq!(
+            debugger.execute("signal 20")?.unwrap(),
+            "signal strength during cycle 20: 420"
+        );
+        assert!(debugger.execute("signal 100").is_err());
+
+        debugger.execute("d 2")?;
+        debugger.execute("b x < 0")?;
+        debugger.execute("c")?;
+        assert!(debugger.registers()[X] < 0);
+        debugger.execute("d 2")?;
+        let output = debugger.execute("c")?.unwrap();
+        assert!(output.contains("program ended"));
+        assert_eq!(debugger.cycle(), 240);
+        assert_eq!(debugger.execute("q")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn partial_screen() -> aoc22::MyResult<()> {
+        let program = example();
+        let mut debugger = Debugger::new(&program, Crt::default());
+        debugger.execute("s 45")?;
+        assert_eq!(
+            debugger.execute("crt")?.unwrap(),
+            format!(
+                "{}{}\n",
+                &EXAMPLE2_EXPECTED[..41],
+                &EXAMPLE2_EXPECTED[41..46]
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn configured_screen() -> aoc22::MyResult<()> {
+        let program = example();
+        let crt =
+            Crt::from_args(&["--width", "10", "--height", "2", "--sprite", "1"].map(String::from))?;
+        let mut debugger = Debugger::new(&program, crt);
+        debugger.execute("s 12")?;
+        // x is 1, 16, 5, 11, 8 for two cycles each, and only covers one pixel
+        assert_eq!(debugger.execute("crt")?.unwrap(), ".#...#..#.\n..\n");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod crt_tests {
+    use super::*;
+
+    const EXAMPLE: &str = include_str!("../inputs/example10.txt");
+
+    fn screen(states: &[i32], width: usize, height: usize, sprite_width: usize) -> String {
+        let crt = Crt {
+            width,
+            height,
+            sprite_width,
+            ..Crt::default()
+        };
+        crt.draw(states).unwrap().to_ascii()
+    }
+
+    #[test]
+    fn huge_screens_and_sprites() {
+        let crt = Crt {
+            width: usize::MAX,
+            height: 2,
+            ..Crt::default()
+        };
+        assert!(crt.draw(&[1, 1]).is_err());
+        assert!(Crt {
+            width: 0,
+            ..Crt::default()
+        }
+        .draw(&[1, 1])
+        .is_err());
+        // x and the sprite at their limits don't overflow
+        assert_eq!(
+            screen(&[0, i32::MIN, i32::MAX, 0], 3, 1, usize::MAX),
+            "###\n"
+        );
+        assert_eq!(screen(&[0, i32::MAX, i32::MIN, 1], 3, 1, 3), "..#\n");
+    }
+
+    #[test]
+    fn geometry() -> aoc22::MyResult<()> {
+        let (_, default) = run_crt(EXAMPLE, &Crt::default())?;
+        assert_eq!(default.to_ascii(), EXAMPLE2_EXPECTED);
+
+        // x stays at 3
+        let states = [3; 13];
+        assert_eq!(screen(&states, 6, 2, 3), "..###.\n..###.\n");
+        assert_eq!(screen(&states, 6, 2, 1), "...#..\n...#..\n");
+        assert_eq!(screen(&states, 6, 2, 4), "..####\n..####\n");
+        assert_eq!(screen(&states, 6, 2, 0), "......\n......\n");
+        assert_eq!(screen(&states, 4, 4, 5), ".###\n.###\n.###\n");
+        // cycles past the end of the screen aren't drawn
+        assert_eq!(screen(&states, 6, 1, 3), "..###.\n");
+        Ok(())
+    }
+
+    #[test]
+    fn sampling() -> aoc22::MyResult<()> {
+        let crt = Crt {
+            sampling_cycles: vec![20, 220],
+            ..Crt::default()
+        };
+        assert_eq!(run_crt(EXAMPLE, &crt)?.0, 420 + 3960);
+        let args = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+        assert_eq!(
+            Crt::from_args(&args("--width 20 --sample 20,220"))?,
+            Crt { width: 20, ..crt }
+        );
+        assert!(Crt::from_args(&args("--width 0")).is_err());
+        assert!(Crt::from_args(&args("--sprite")).is_err());
+        assert!(Crt::from_args(&args("--width 18446744073709551615 --height 2")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn backends() -> aoc22::MyResult<()> {
+        let screen = Screen {
+            width: 3,
+            height: 3,
+            pixels: vec![true, false, true, true, true, false, false, true],
+        };
+        assert_eq!(screen.to_ascii(), "#.#\n##.\n.#\n");
+        assert_eq!(screen.to_blocks(), "█ █\n██ \n █\n");
+        assert_eq!(screen.to_half_blocks(), "█▄▀\n ▀\n");
+        let mut pbm = Vec::new();
+        screen.write_pbm(&mut pbm)?;
+        assert_eq!(String::from_utf8(pbm)?, "P1\n3 3\n1 0 1\n1 1 0\n0 1 0\n");
+        assert_eq!(
+            Backend::from_name("pbm:crt.pbm")?,
+            Backend::Pbm("crt.pbm".into())
+        );
+        assert!(Backend::from_name("svg").is_err());
+        Ok(())
+    }
 }
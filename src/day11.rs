@@ -1,46 +1,179 @@
-use std::{collections::VecDeque, fmt::Debug, rc::Rc, str::FromStr};
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt::{Debug, Display},
+    io::{self, Write},
+    iter::Peekable,
+    ops::RangeInclusive,
+    str::{CharIndices, FromStr},
+    sync::Arc,
+};
 
 use itertools::Itertools;
 
-aoc22::main!(day11, "../inputs/input11.txt");
+fn main() -> aoc22::MyResult<()> {
+    // the monkey business after any number of rounds, with the rules of part 2, is given by
+    // `cargo run --bin day11 -- <rounds>`
+    // what happens in the first rounds of part 1 is told like in the puzzle with
+    // `cargo run --bin day11 -- narrate [rounds]`,
+    // and the events of any part written as JSON lines with
+    // `cargo run --bin day11 -- events <part> <rounds> [--monkeys <m>,<m>,...] [--rounds <first>-<last>]`
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let input = include_str!("../inputs/input11.txt");
+    match &args[..] {
+        [command, rest @ ..] if command == "narrate" && rest.len() <= 1 => {
+            let rounds = rest.first().map_or(Ok(1), |r| r.parse())?;
+            let mut monkeys = parse_monkeys(input)?;
+            for round in 1..=rounds {
+                let mut log = EventLog::default();
+                play_round(&mut monkeys, round, Relief::DivideBy(3), &mut log)?;
+                print!("{}", log.narrate(&monkeys, Relief::DivideBy(3)));
+                println!("\n{}", round_recap(round, &monkeys));
+            }
+            return Ok(());
+        }
+        [command, part, rounds, filter_args @ ..] if command == "events" => {
+            let filter = EventFilter::from_args(filter_args)?;
+            let mut sink = JsonLines::new(io::stdout().lock(), filter);
+            let monkeys = parse_monkeys(input)?;
+            match part.as_str() {
+                "1" => {
+                    let mut monkeys = monkeys;
+                    for round in 1..=rounds.parse()? {
+                        play_round(&mut monkeys, round, Relief::DivideBy(3), &mut sink)?;
+                    }
+                }
+                "2" => {
+                    let mut monkeys = with_residues(monkeys)?;
+                    for round in 1..=rounds.parse()? {
+                        play_round(&mut monkeys, round, Relief::None, &mut sink)?;
+                    }
+                }
+                _ => return Err(format!("No part {}", part).into()),
+            }
+            sink.finish()?.flush()?;
+            return Ok(());
+        }
+        _ => (),
+    }
+    if let [rounds] = &args[..] {
+        let monkeys = with_residues(parse_monkeys(input)?)?;
+        let inspections = extrapolate_inspections(&monkeys, rounds.parse()?)?;
+        println!("{}", business_of(&inspections)?);
+        return Ok(());
+    }
+    let (part1, part2) = day11(input)?;
+    println!("part1: {}", part1);
+    println!("part2: {}", part2);
+    Ok(())
+}
 
 aoc22::test_with_example!(day11, "../inputs/example11.txt", 10605, 2713310158);
 
 pub fn day11(input: &str) -> aoc22::MyResult<(u64, u64)> {
-    let monkeys = input
-        .split("\r\n\r\n")
-        .map(Monkey::from_str)
-        .collect::<Result<Vec<_>, _>>()?;
+    let monkeys = parse_monkeys(input)?;
 
-    let part1 = monkey_business(monkeys.clone(), 20, &|worry| worry / 3)?;
+    let part1 = monkey_business(monkeys.clone(), 20, Relief::DivideBy(3))?;
 
-    let lcm = monkeys.iter().map(|m| m.prime_factor).product();
-    let part2 = monkey_business(monkeys, 10000, &|worry| decrease_worry_with_lcm(worry, lcm))?;
+    // no need to calm down if we only remember what the monkeys test
+    let part2 = monkey_business(with_residues(monkeys)?, 10000, Relief::None)?;
 
     Ok((part1, part2))
 }
 
-/// use prime numbers to fight anxiety
-fn decrease_worry_with_lcm(worry: u64, lcm: u64) -> u64 {
-    if worry > lcm {
-        worry % lcm + lcm
-    } else {
-        worry
+/// Parse the notes about every monkey, numbered from 0 in order, throwing only to each other
+fn parse_monkeys(input: &str) -> Result<Vec<Monkey>, MonkeyError> {
+    let mut lines = note_lines(input);
+    let mut monkeys = Vec::new();
+    // line of the header of each monkey, to report bad throw targets
+    let mut headers = Vec::new();
+    while let Some(&(line, header)) = lines.peek() {
+        if parse_header(line, header)? != monkeys.len() {
+            return Err(MonkeyError::OutOfSequence {
+                line,
+                expected: monkeys.len(),
+                found: header.to_string(),
+            });
+        }
+        headers.push(line);
+        monkeys.push(parse_monkey(&mut lines)?);
+    }
+    for (index, (monkey, line)) in monkeys.iter().zip(headers).enumerate() {
+        for target in [monkey.target.0, monkey.target.1] {
+            // the monkey would keep catching the item it throws
+            if target == index {
+                return Err(MonkeyError::ThrowsToItself {
+                    line,
+                    monkey: index,
+                });
+            }
+            if target >= monkeys.len() {
+                return Err(MonkeyError::UnknownTarget {
+                    line,
+                    target,
+                    monkeys: monkeys.len(),
+                });
+            }
+        }
+    }
+    Ok(monkeys)
+}
+
+/// What happens to the worry level of an item after a monkey inspected it
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Relief {
+    None,
+    /// the monkey gets bored with the item
+    DivideBy(u64),
+}
+
+impl Relief {
+    fn apply<W: Worry>(self, worry: W) -> Result<W, WorryError> {
+        match self {
+            Relief::None => Ok(worry),
+            Relief::DivideBy(n) => worry.apply(BinOp::Div, &worry.constant(n)),
+        }
+    }
+}
+
+fn play_round<W: Worry>(
+    monkeys: &mut [Monkey<W>],
+    round: usize,
+    relief: Relief,
+    sink: &mut impl EventSink<W>,
+) -> Result<(), WorryError> {
+    for monkey_index in 0..monkeys.len() {
+        sink.record(Event {
+            round,
+            monkey: monkey_index,
+            kind: EventKind::Turn,
+        });
+        while let Some((item, target)) = monkeys[monkey_index].inspect(relief, &mut |kind| {
+            sink.record(Event {
+                round,
+                monkey: monkey_index,
+                kind,
+            })
+        })? {
+            monkeys[target].catch(item, &mut |kind| {
+                sink.record(Event {
+                    round,
+                    monkey: target,
+                    kind,
+                })
+            });
+        }
     }
+    Ok(())
 }
 
-fn monkey_business(
-    mut monkeys: Vec<Monkey>,
+fn monkey_business<W: Worry>(
+    mut monkeys: Vec<Monkey<W>>,
     round_count: usize,
-    decrease_worry: &impl Fn(u64) -> u64,
+    relief: Relief,
 ) -> aoc22::MyResult<u64> {
     for round in 1..=round_count {
-        for monkey_index in 0..monkeys.len() {
-            while let Some((item, target)) = monkeys[monkey_index].inspect(decrease_worry) {
-                monkeys[target].catch(item);
-            }
-        }
-        round_recap(round, &monkeys);
+        play_round(&mut monkeys, round, relief, &mut ())?;
     }
 
     let business = monkeys
@@ -55,128 +188,1420 @@ fn monkey_business(
     Ok(business)
 }
 
-#[derive(Clone)]
-struct Monkey {
-    items: VecDeque<u64>,
-    operation: Rc<dyn Fn(u64) -> u64>,
-    prime_factor: u64,
+#[derive(Debug, Clone, PartialEq)]
+struct Monkey<W = u64> {
+    items: VecDeque<W>,
+    operation: Expr,
+    divisor: u64,
     target: (usize, usize),
     inspections: u64,
 }
 
-impl Monkey {
-    fn inspect(&mut self, decrease_worry: impl Fn(u64) -> u64) -> Option<(u64, usize)> {
-        let mut item = self.items.pop_front()?;
+impl<W: Worry> Monkey<W> {
+    fn inspect(
+        &mut self,
+        relief: Relief,
+        emit: &mut impl FnMut(EventKind<&W>),
+    ) -> Result<Option<(W, usize)>, WorryError> {
+        let Some(mut item) = self.items.pop_front() else {
+            return Ok(None);
+        };
+        emit(EventKind::Inspect { worry: &item });
         self.inspections += 1;
-        item = (self.operation)(item);
-        item = decrease_worry(item);
-        if item % self.prime_factor == 0 {
-            Some((item, self.target.0))
-        } else {
-            Some((item, self.target.1))
+        item = self.operation.eval(&item)?;
+        emit(EventKind::Operation { worry: &item });
+        if relief != Relief::None {
+            item = relief.apply(item)?;
+            emit(EventKind::Relief { worry: &item });
         }
+        let divisible = item.is_divisible_by(self.divisor)?;
+        emit(EventKind::Test {
+            divisor: self.divisor,
+            divisible,
+        });
+        let target = if divisible {
+            self.target.0
+        } else {
+            self.target.1
+        };
+        emit(EventKind::Throw {
+            worry: &item,
+            target,
+        });
+        Ok(Some((item, target)))
     }
 
-    fn catch(&mut self, item: u64) {
+    fn catch(&mut self, item: W, emit: &mut impl FnMut(EventKind<&W>)) {
+        emit(EventKind::Catch { worry: &item });
         self.items.push_back(item);
     }
 }
 
-impl Debug for Monkey {
+#[derive(Debug, Clone, PartialEq)]
+enum EventKind<W> {
+    /// a monkey starts its turn
+    Turn,
+    Inspect {
+        worry: W,
+    },
+    /// the worry level after the operation of the monkey
+    Operation {
+        worry: W,
+    },
+    Relief {
+        worry: W,
+    },
+    Test {
+        divisor: u64,
+        divisible: bool,
+    },
+    Throw {
+        worry: W,
+        target: usize,
+    },
+    Catch {
+        worry: W,
+    },
+}
+
+/// Something that happened to a monkey during a round
+#[derive(Debug, Clone, PartialEq)]
+struct Event<W> {
+    round: usize,
+    monkey: usize,
+    kind: EventKind<W>,
+}
+
+impl<W: Clone> Event<&W> {
+    fn cloned(&self) -> Event<W> {
+        let kind = match self.kind {
+            EventKind::Turn => EventKind::Turn,
+            EventKind::Inspect { worry } => EventKind::Inspect {
+                worry: worry.clone(),
+            },
+            EventKind::Operation { worry } => EventKind::Operation {
+                worry: worry.clone(),
+            },
+            EventKind::Relief { worry } => EventKind::Relief {
+                worry: worry.clone(),
+            },
+            EventKind::Test { divisor, divisible } => EventKind::Test { divisor, divisible },
+            EventKind::Throw { worry, target } => EventKind::Throw {
+                worry: worry.clone(),
+                target,
+            },
+            EventKind::Catch { worry } => EventKind::Catch {
+                worry: worry.clone(),
+            },
+        };
+        Event {
+            round: self.round,
+            monkey: self.monkey,
+            kind,
+        }
+    }
+}
+
+impl<W: Display> Event<W> {
+    /// The event as a line of JSON, worry levels being strings since they may not fit in a double
+    fn to_json(&self) -> String {
+        let fields = match &self.kind {
+            EventKind::Turn => r#""event":"turn""#.to_string(),
+            EventKind::Inspect { worry } => format!(r#""event":"inspect","worry":"{}""#, worry),
+            EventKind::Operation { worry } => {
+                format!(r#""event":"operation","worry":"{}""#, worry)
+            }
+            EventKind::Relief { worry } => format!(r#""event":"relief","worry":"{}""#, worry),
+            EventKind::Test { divisor, divisible } => format!(
+                r#""event":"test","divisor":{},"divisible":{}"#,
+                divisor, divisible
+            ),
+            EventKind::Throw { worry, target } => {
+                format!(r#""event":"throw","worry":"{}","target":{}"#, worry, target)
+            }
+            EventKind::Catch { worry } => format!(r#""event":"catch","worry":"{}""#, worry),
+        };
+        format!(
+            r#"{{"round":{},"monkey":{},{}}}"#,
+            self.round, self.monkey, fields
+        )
+    }
+}
+
+/// Which events to keep
+#[derive(Debug, Clone, PartialEq)]
+struct EventFilter {
+    /// None for every monkey
+    monkeys: Option<Vec<usize>>,
+    rounds: RangeInclusive<usize>,
+}
+
+impl Default for EventFilter {
+    fn default() -> Self {
+        EventFilter {
+            monkeys: None,
+            rounds: 1..=usize::MAX,
+        }
+    }
+}
+
+impl EventFilter {
+    /// `[--monkeys <m>,<m>,...] [--rounds <first>-<last>]`
+    fn from_args(args: &[String]) -> aoc22::MyResult<EventFilter> {
+        let mut filter = EventFilter::default();
+        for option in args.chunks(2) {
+            match option {
+                [flag, monkeys] if flag == "--monkeys" => {
+                    filter.monkeys = Some(
+                        monkeys
+                            .split(',')
+                            .map(str::parse)
+                            .collect::<Result<_, _>>()?,
+                    )
+                }
+                [flag, rounds] if flag == "--rounds" => {
+                    let (first, last) = rounds.split_once('-').unwrap_or((rounds, rounds));
+                    filter.rounds = first.parse()?..=last.parse()?;
+                }
+                _ => return Err(format!("Bad event filter {:?}", option).into()),
+            }
+        }
+        Ok(filter)
+    }
+
+    fn accepts<W>(&self, event: &Event<W>) -> bool {
+        self.rounds.contains(&event.round)
+            && self
+                .monkeys
+                .as_ref()
+                .is_none_or(|monkeys| monkeys.contains(&event.monkey))
+    }
+}
+
+/// Where the events of a simulation go
+trait EventSink<W> {
+    fn record(&mut self, event: Event<&W>);
+}
+
+/// Nowhere
+impl<W> EventSink<W> for () {
+    fn record(&mut self, _: Event<&W>) {}
+}
+
+/// Events kept in memory
+#[derive(Debug, Default)]
+struct EventLog<W> {
+    filter: EventFilter,
+    events: Vec<Event<W>>,
+}
+
+impl<W: Clone> EventSink<W> for EventLog<W> {
+    fn record(&mut self, event: Event<&W>) {
+        if self.filter.accepts(&event) {
+            self.events.push(event.cloned());
+        }
+    }
+}
+
+impl<W: Display> EventLog<W> {
+    /// The events told like in the puzzle
+    fn narrate(&self, monkeys: &[Monkey<W>], relief: Relief) -> String {
+        let mut text = String::new();
+        for event in &self.events {
+            let line = match &event.kind {
+                EventKind::Turn => format!("Monkey {}:", event.monkey),
+                EventKind::Inspect { worry } => {
+                    format!("  Monkey inspects an item with a worry level of {}.", worry)
+                }
+                EventKind::Operation { worry } => format!(
+                    "    Worry level {} to {}.",
+                    describe_operation(&monkeys[event.monkey].operation),
+                    worry
+                ),
+                EventKind::Relief { worry } => match relief {
+                    Relief::DivideBy(n) => format!(
+                        "    Monkey gets bored with item. Worry level is divided by {} to {}.",
+                        n, worry
+                    ),
+                    Relief::None => continue,
+                },
+                EventKind::Test { divisor, divisible } => format!(
+                    "    Current worry level {} divisible by {}.",
+                    if *divisible { "is" } else { "is not" },
+                    divisor
+                ),
+                EventKind::Throw { worry, target } => format!(
+                    "    Item with worry level {} is thrown to monkey {}.",
+                    worry, target
+                ),
+                EventKind::Catch { .. } => continue,
+            };
+            text += &line;
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// What the operation does to the worry level, in the words of the puzzle when possible
+fn describe_operation(operation: &Expr) -> String {
+    match operation {
+        Expr::BinOp(a, op, b) if **a == Expr::Old => match (op, &**b) {
+            (BinOp::Mul, Expr::Old) => "is multiplied by itself".to_string(),
+            (BinOp::Mul, Expr::Literal(n)) => format!("is multiplied by {}", n),
+            (BinOp::Add, Expr::Old) => "increases by itself".to_string(),
+            (BinOp::Add, Expr::Literal(n)) => format!("increases by {}", n),
+            _ => format!("becomes {}", operation),
+        },
+        _ => format!("becomes {}", operation),
+    }
+}
+
+/// Events written as JSON lines as they happen
+struct JsonLines<Out: Write> {
+    filter: EventFilter,
+    out: Out,
+    /// the first write error, reported by `finish`
+    error: Option<io::Error>,
+}
+
+impl<Out: Write> JsonLines<Out> {
+    fn new(out: Out, filter: EventFilter) -> Self {
+        JsonLines {
+            filter,
+            out,
+            error: None,
+        }
+    }
+
+    fn finish(mut self) -> io::Result<Out> {
+        match self.error {
+            Some(e) => Err(e),
+            None => self.out.flush().map(|()| self.out),
+        }
+    }
+}
+
+impl<W: Display, Out: Write> EventSink<W> for JsonLines<Out> {
+    fn record(&mut self, event: Event<&W>) {
+        if self.error.is_none() && self.filter.accepts(&event) {
+            if let Err(e) = writeln!(self.out, "{}", event.to_json()) {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+impl Monkey {
+    /// The same monkey, holding items with another representation of their worry level
+    fn map_items<W, E>(self, convert: impl FnMut(u64) -> Result<W, E>) -> Result<Monkey<W>, E> {
+        Ok(Monkey {
+            items: self
+                .items
+                .into_iter()
+                .map(convert)
+                .collect::<Result<_, _>>()?,
+            operation: self.operation,
+            divisor: self.divisor,
+            target: self.target,
+            inspections: self.inspections,
+        })
+    }
+}
+
+/// Replace the worry levels of the items of every monkey with their residues modulo every divisor
+fn with_residues(monkeys: Vec<Monkey>) -> Result<Vec<Monkey<Residues>>, WorryError> {
+    let moduli: Arc<[u64]> = monkeys.iter().map(|m| m.divisor).collect();
+    monkeys
+        .into_iter()
+        .map(|monkey| monkey.map_items(|item| Residues::new(item, moduli.clone())))
+        .collect()
+}
+
+/// Product of the inspection counts of the two most active monkeys
+fn business_of(inspections: &[u128]) -> aoc22::MyResult<u128> {
+    let mut counts = inspections.to_vec();
+    counts.sort_unstable_by(|a, b| b.cmp(a));
+    match counts[..] {
+        [first, second, ..] => Ok(first.checked_mul(second).ok_or(WorryError::Overflow)?),
+        _ => Err("No monke ?".into()),
+    }
+}
+
+/// Number of inspections of every monkey after any number of rounds, without relief.
+/// Items never interact, so each one is followed on its own: its owner and residues at the start
+/// of a round determine the rest of its journey, which repeats as soon as that state does.
+fn extrapolate_inspections(
+    monkeys: &[Monkey<Residues>],
+    rounds: u64,
+) -> Result<Vec<u128>, WorryError> {
+    let mut total = vec![0; monkeys.len()];
+    for (owner, monkey) in monkeys.iter().enumerate() {
+        for item in &monkey.items {
+            let counts = follow_item(monkeys, owner, item.clone(), rounds)?;
+            total.iter_mut().zip(counts).for_each(|(t, c)| *t += c);
+        }
+    }
+    Ok(total)
+}
+
+/// Inspections of a single item by every monkey, over the given number of rounds.
+/// There are fewer than `monkeys.len()` per round, so any number of rounds fits in 128 bits.
+fn follow_item(
+    monkeys: &[Monkey<Residues>],
+    mut owner: usize,
+    mut worry: Residues,
+    rounds: u64,
+) -> Result<Vec<u128>, WorryError> {
+    // round at which each state was first seen at the start of a round
+    let mut seen: HashMap<(usize, Residues), u64> = HashMap::new();
+    // inspections by each monkey before each round
+    let mut history: Vec<Vec<u128>> = vec![vec![0; monkeys.len()]];
+    for round in 0..rounds {
+        if let Some(&cycle_start) = seen.get(&(owner, worry.clone())) {
+            let (start, now) = (&history[cycle_start as usize], &history[round as usize]);
+            let cycle_length = round - cycle_start;
+            let (cycles, rest) = (
+                ((rounds - round) / cycle_length) as u128,
+                (rounds - round) % cycle_length,
+            );
+            let partial = &history[(cycle_start + rest) as usize];
+            return Ok((0..monkeys.len())
+                .map(|m| now[m] + cycles * (now[m] - start[m]) + (partial[m] - start[m]))
+                .collect());
+        }
+        seen.insert((owner, worry.clone()), round);
+
+        let mut counts = history[round as usize].clone();
+        // monkeys take their turns in order, so the item can be inspected again in the same round
+        // as long as it's thrown to a monkey that hasn't played yet
+        loop {
+            let monkey = &monkeys[owner];
+            counts[owner] += 1;
+            worry = monkey.operation.eval(&worry)?;
+            let target = if worry.is_divisible_by(monkey.divisor)? {
+                monkey.target.0
+            } else {
+                monkey.target.1
+            };
+            let next_turn = target > owner;
+            owner = target;
+            if !next_turn {
+                break;
+            }
+        }
+        history.push(counts);
+    }
+    Ok(history.pop().unwrap())
+}
+
+#[derive(Debug, PartialEq)]
+enum WorryError {
+    Overflow,
+    DivisionByZero,
+    /// An operation that the representation of worry levels can't do
+    Unsupported(&'static str),
+}
+
+impl Display for WorryError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Monkey")
-            .field("items", &self.items)
-            //.field("operation", &self.operation)
-            .field("prime_factor", &self.prime_factor)
-            .field("target", &self.target)
-            .finish()
+        match self {
+            Self::Overflow => write!(f, "worry level overflow"),
+            Self::DivisionByZero => write!(f, "division by zero"),
+            Self::Unsupported(what) => write!(f, "unsupported: {}", what),
+        }
+    }
+}
+
+impl Error for WorryError {}
+
+/// Worry levels, or anything that can tell the monkeys what they need to know about them
+trait Worry: Clone + Debug {
+    /// A constant, with the same representation as self
+    fn constant(&self, n: u64) -> Self;
+    fn apply(&self, op: BinOp, other: &Self) -> Result<Self, WorryError>;
+    fn is_divisible_by(&self, divisor: u64) -> Result<bool, WorryError>;
+}
+
+macro_rules! impl_worry {
+    ($($t:ty),*) => {
+        $(
+            impl Worry for $t {
+                fn constant(&self, n: u64) -> Self {
+                    <$t>::from(n)
+                }
+                fn apply(&self, op: BinOp, other: &Self) -> Result<Self, WorryError> {
+                    match op {
+                        BinOp::Add => self.checked_add(*other).ok_or(WorryError::Overflow),
+                        BinOp::Sub => self.checked_sub(*other).ok_or(WorryError::Overflow),
+                        BinOp::Mul => self.checked_mul(*other).ok_or(WorryError::Overflow),
+                        BinOp::Div => self.checked_div(*other).ok_or(WorryError::DivisionByZero),
+                    }
+                }
+                fn is_divisible_by(&self, divisor: u64) -> Result<bool, WorryError> {
+                    match divisor {
+                        0 => Err(WorryError::DivisionByZero),
+                        _ => Ok(self % <$t>::from(divisor) == 0),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_worry!(u64, u128);
+
+/// A worry level stored as its remainders modulo a few numbers, which never overflows
+/// but only knows about divisibility by these numbers
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Residues {
+    moduli: Arc<[u64]>,
+    residues: Vec<u64>,
+}
+
+impl Residues {
+    fn new(n: u64, moduli: Arc<[u64]>) -> Result<Residues, WorryError> {
+        if moduli.contains(&0) {
+            return Err(WorryError::DivisionByZero);
+        }
+        let residues = moduli.iter().map(|m| n % m).collect();
+        Ok(Residues { moduli, residues })
+    }
+}
+
+/// `r mod m` for each modulus
+impl Display for Residues {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let residues = self
+            .residues
+            .iter()
+            .zip(self.moduli.iter())
+            .map(|(r, m)| format!("{} mod {}", r, m));
+        write!(f, "{}", residues.format(", "))
+    }
+}
+
+impl Worry for Residues {
+    fn constant(&self, n: u64) -> Self {
+        Residues {
+            moduli: self.moduli.clone(),
+            residues: self.moduli.iter().map(|m| n % m).collect(),
+        }
+    }
+
+    fn apply(&self, op: BinOp, other: &Self) -> Result<Self, WorryError> {
+        let residues = self
+            .moduli
+            .iter()
+            .zip(self.residues.iter().zip(&other.residues))
+            .map(|(&m, (&a, &b))| {
+                let (m, a, b) = (m as u128, a as u128, b as u128);
+                match op {
+                    BinOp::Add => Ok((a + b) % m),
+                    BinOp::Sub => Ok((a + m - b) % m),
+                    BinOp::Mul => Ok((a * b) % m),
+                    BinOp::Div => Err(WorryError::Unsupported("division of residues")),
+                }
+                .map(|r| r as u64)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Residues {
+            moduli: self.moduli.clone(),
+            residues,
+        })
+    }
+
+    fn is_divisible_by(&self, divisor: u64) -> Result<bool, WorryError> {
+        self.moduli
+            .iter()
+            .position(|&m| m == divisor)
+            .map(|i| self.residues[i] == 0)
+            .ok_or(WorryError::Unsupported(
+                "divisibility by a number that isn't a modulus",
+            ))
     }
 }
 
 impl FromStr for Monkey {
-    type Err = Box<dyn std::error::Error>;
+    type Err = MonkeyError;
 
+    /// The notes about a single monkey, whatever its number
     fn from_str(description: &str) -> Result<Self, Self::Err> {
-        let lines: Vec<_> = description.trim().lines().collect();
+        let mut lines = note_lines(description);
+        let monkey = parse_monkey(&mut lines)?;
+        match lines.next() {
+            None => Ok(monkey),
+            Some((line, found)) => Err(MonkeyError::Malformed {
+                line,
+                expected: "the end of the notes",
+                found: found.to_string(),
+            }),
+        }
+    }
+}
 
-        let items = match lines[1].trim().split(&[' ', ',']).collect::<Vec<_>>()[..] {
-            ["Starting", "items:", ref items_str @ ..] => items_str
-                .iter()
-                .filter(|s| !s.is_empty())
-                .map(|s| s.parse())
-                .collect::<Result<VecDeque<_>, _>>()?,
-            ref bad => panic!("Can't parse items : {:?}", bad),
-        };
-        let operation = match lines[2].trim().split(&[' ', ',']).collect::<Vec<_>>()[..] {
-            ["Operation:", "new", "=", a, op, b] => parse_operation(a, op, b)?,
-            ref bad => panic!("Can't parse operation : {:?}", bad),
-        };
-        let prime_factor = match lines[3].trim().split(&[' ', ',']).collect::<Vec<_>>()[..] {
-            ["Test:", "divisible", "by", n] => n.parse()?,
-            ref bad => panic!("Can't parse operation : {:?}", bad),
-        };
-        let monkey_true: usize = lines[4]
+#[derive(Debug, PartialEq)]
+enum MonkeyError {
+    /// The notes end in the middle of a monkey
+    MissingLine {
+        expected: &'static str,
+    },
+    /// A line that isn't the expected one (1-based line number)
+    Malformed {
+        line: usize,
+        expected: &'static str,
+        found: String,
+    },
+    BadNumber {
+        line: usize,
+        found: String,
+    },
+    BadOperation {
+        line: usize,
+        error: ExprError,
+    },
+    /// The monkeys aren't numbered 0, 1, 2...
+    OutOfSequence {
+        line: usize,
+        expected: usize,
+        found: String,
+    },
+    /// The monkey whose header is on this line throws to a monkey that doesn't exist
+    UnknownTarget {
+        line: usize,
+        target: usize,
+        monkeys: usize,
+    },
+    ThrowsToItself {
+        line: usize,
+        monkey: usize,
+    },
+}
+
+impl Display for MonkeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingLine { expected } => {
+                write!(f, "unexpected end of the notes, expected {}", expected)
+            }
+            Self::Malformed {
+                line,
+                expected,
+                found,
+            } => write!(f, "line {}: expected {}, found {:?}", line, expected, found),
+            Self::BadNumber { line, found } => {
+                write!(f, "line {}: {:?} is not a number", line, found)
+            }
+            Self::BadOperation { line, error } => write!(f, "line {}: {}", line, error),
+            Self::OutOfSequence {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: expected the notes about monkey {}, found {:?}",
+                line, expected, found
+            ),
+            Self::UnknownTarget {
+                line,
+                target,
+                monkeys,
+            } => write!(
+                f,
+                "line {}: this monkey throws to monkey {}, but there are only {} monkeys",
+                line, target, monkeys
+            ),
+            Self::ThrowsToItself { line, monkey } => {
+                write!(f, "line {}: monkey {} throws to itself", line, monkey)
+            }
+        }
+    }
+}
+
+impl Error for MonkeyError {}
+
+/// Non-blank lines of the notes with their 1-based number, without indentation or `\r`
+fn note_lines(notes: &str) -> Peekable<impl Iterator<Item = (usize, &str)>> {
+    notes
+        .lines()
+        .enumerate()
+        .map(|(n, l)| (n + 1, l.trim()))
+        .filter(|(_, l)| !l.is_empty())
+        .peekable()
+}
+
+fn parse_number<T: FromStr>(line: usize, s: &str) -> Result<T, MonkeyError> {
+    s.parse().map_err(|_| MonkeyError::BadNumber {
+        line,
+        found: s.to_string(),
+    })
+}
+
+/// The number ending a value made of the given words
+fn parse_sentence<T: FromStr>(
+    (line, value): (usize, &str),
+    words: &'static str,
+) -> Result<T, MonkeyError> {
+    match value.rsplit_once(char::is_whitespace) {
+        Some((start, n)) if start.split_whitespace().eq(words.split(' ')) => parse_number(line, n),
+        _ => Err(MonkeyError::Malformed {
+            line,
+            expected: words,
+            found: value.to_string(),
+        }),
+    }
+}
+
+/// Number of the monkey in a `Monkey N:` header
+fn parse_header(line: usize, header: &str) -> Result<usize, MonkeyError> {
+    match header.split_whitespace().collect::<Vec<_>>()[..] {
+        ["Monkey", number] if number.ends_with(':') => {
+            parse_number(line, number.trim_end_matches(':'))
+        }
+        _ => Err(MonkeyError::Malformed {
+            line,
+            expected: "\"Monkey <n>:\"",
+            found: header.to_string(),
+        }),
+    }
+}
+
+/// The header and the five lines of notes about a monkey
+fn parse_monkey<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+) -> Result<Monkey, MonkeyError> {
+    let (line, header) = lines.next().ok_or(MonkeyError::MissingLine {
+        expected: "\"Monkey <n>:\"",
+    })?;
+    parse_header(line, header)?;
+
+    // the value of the next line, which must start with `<name>:`
+    let mut field = |name: &'static str| match lines.next() {
+        Some((line, text)) => match text.split_once(':') {
+            Some((key, value)) if key.split_whitespace().eq(name.split(' ')) => {
+                Ok((line, value.trim()))
+            }
+            _ => Err(MonkeyError::Malformed {
+                line,
+                expected: name,
+                found: text.to_string(),
+            }),
+        },
+        None => Err(MonkeyError::MissingLine { expected: name }),
+    };
+    let (line, items) = field("Starting items")?;
+    let items = items
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| parse_number(line, s))
+        .collect::<Result<_, _>>()?;
+    let (line, operation) = field("Operation")?;
+    let operation = match operation.split_once('=') {
+        Some((new, expression)) if new.trim() == "new" => expression
             .trim()
-            .split(&[' ', ','])
-            .last()
-            .ok_or("If true parsing error")?
-            .parse()?;
-        let monkey_false: usize = lines[5]
-            .split(&[' ', ','])
-            .last()
-            .ok_or("If false parsing error")?
-            .parse()?;
+            .parse()
+            .map_err(|error| MonkeyError::BadOperation { line, error })?,
+        _ => {
+            return Err(MonkeyError::Malformed {
+                line,
+                expected: "new = <expression>",
+                found: operation.to_string(),
+            })
+        }
+    };
+    let divisor = parse_sentence(field("Test")?, "divisible by")?;
+    let monkey_true = parse_sentence(field("If true")?, "throw to monkey")?;
+    let monkey_false = parse_sentence(field("If false")?, "throw to monkey")?;
 
-        Ok(Monkey {
-            items,
-            operation,
-            prime_factor,
-            target: (monkey_true, monkey_false),
-            inspections: 0,
-        })
+    Ok(Monkey {
+        items,
+        operation,
+        divisor,
+        target: (monkey_true, monkey_false),
+        inspections: 0,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl BinOp {
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Add | BinOp::Sub => 0,
+            BinOp::Mul | BinOp::Div => 1,
+        }
+    }
+
+    fn symbol(self) -> char {
+        match self {
+            BinOp::Add => '+',
+            BinOp::Sub => '-',
+            BinOp::Mul => '*',
+            BinOp::Div => '/',
+        }
+    }
+
+    fn from_symbol(c: char) -> Option<BinOp> {
+        [BinOp::Add, BinOp::Sub, BinOp::Mul, BinOp::Div]
+            .into_iter()
+            .find(|op| op.symbol() == c)
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum Operand {
-    Literal(u64),
+/// The operation of a monkey, computing the new worry level from the old one
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
     Old,
+    Literal(u64),
+    BinOp(Box<Expr>, BinOp, Box<Expr>),
 }
 
-impl Operand {
-    fn get(self, old: u64) -> u64 {
+impl Expr {
+    fn eval<W: Worry>(&self, old: &W) -> Result<W, WorryError> {
+        match self {
+            Expr::Old => Ok(old.clone()),
+            Expr::Literal(n) => Ok(old.constant(*n)),
+            Expr::BinOp(a, op, b) => a.eval(old)?.apply(*op, &b.eval(old)?),
+        }
+    }
+
+    /// Write a side of an operation, with parentheses if it would be parsed differently without
+    fn fmt_operand(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        parent: BinOp,
+        right: bool,
+    ) -> std::fmt::Result {
         match self {
-            Self::Literal(n) => n,
-            Self::Old => old,
+            Expr::BinOp(_, op, _)
+                if op.precedence() < parent.precedence()
+                    || (right && op.precedence() == parent.precedence()) =>
+            {
+                write!(f, "({})", self)
+            }
+            _ => write!(f, "{}", self),
         }
     }
 }
 
-impl FromStr for Operand {
-    type Err = Box<dyn std::error::Error>;
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Old => write!(f, "old"),
+            Expr::Literal(n) => write!(f, "{}", n),
+            Expr::BinOp(a, op, b) => {
+                a.fmt_operand(f, *op, false)?;
+                write!(f, " {} ", op.symbol())?;
+                b.fmt_operand(f, *op, true)
+            }
+        }
+    }
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "old" => Ok(Operand::Old),
-            n => Ok(Operand::Literal(n.parse()?)),
+#[derive(Debug, PartialEq)]
+enum ExprError {
+    /// Something that doesn't fit at this position (1-based column) of the expression
+    Unexpected {
+        column: usize,
+        found: String,
+    },
+    UnexpectedEnd,
+}
+
+impl Display for ExprError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unexpected { column, found } => {
+                write!(f, "unexpected {:?} at column {}", found, column)
+            }
+            Self::UnexpectedEnd => write!(f, "unexpected end of expression"),
+        }
+    }
+}
+
+impl Error for ExprError {}
+
+/// Recursive descent parser for expressions, `*` and `/` binding tighter than `+` and `-`
+struct ExprParser<'a> {
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl ExprParser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+    }
+
+    fn peek(&mut self) -> Option<(usize, char)> {
+        self.skip_whitespace();
+        self.chars.peek().copied()
+    }
+
+    fn unexpected(column: usize, found: impl ToString) -> ExprError {
+        ExprError::Unexpected {
+            column: column + 1,
+            found: found.to_string(),
+        }
+    }
+
+    /// A sum or difference of terms
+    fn expression(&mut self) -> Result<Expr, ExprError> {
+        self.operations(0)
+    }
+
+    /// Operations of the given precedence level, and the ones binding tighter
+    fn operations(&mut self, precedence: u8) -> Result<Expr, ExprError> {
+        let operand = |parser: &mut Self| match precedence {
+            0 => parser.operations(1),
+            _ => parser.factor(),
+        };
+        let mut expr = operand(self)?;
+        while let Some(op) = self
+            .peek()
+            .and_then(|(_, c)| BinOp::from_symbol(c))
+            .filter(|op| op.precedence() == precedence)
+        {
+            self.chars.next();
+            expr = Expr::BinOp(Box::new(expr), op, Box::new(operand(self)?));
+        }
+        Ok(expr)
+    }
+
+    /// `old`, a number, or an expression between parentheses
+    fn factor(&mut self) -> Result<Expr, ExprError> {
+        let (column, c) = self.peek().ok_or(ExprError::UnexpectedEnd)?;
+        if c == '(' {
+            self.chars.next();
+            let expr = self.expression()?;
+            return match self.chars.next() {
+                Some((_, ')')) => Ok(expr),
+                Some((column, c)) => Err(Self::unexpected(column, c)),
+                None => Err(ExprError::UnexpectedEnd),
+            };
+        }
+        let mut word = String::new();
+        while let Some((_, c)) = self.chars.next_if(|(_, c)| c.is_ascii_alphanumeric()) {
+            word.push(c);
+        }
+        match word.as_str() {
+            "" => Err(Self::unexpected(column, c)),
+            "old" => Ok(Expr::Old),
+            _ => word
+                .parse()
+                .map(Expr::Literal)
+                .map_err(|_| Self::unexpected(column, word)),
         }
     }
 }
 
-fn parse_operation(a: &str, op: &str, b: &str) -> aoc22::MyResult<Rc<dyn Fn(u64) -> u64>> {
-    let a: Operand = a.parse()?;
-    let b: Operand = b.parse()?;
-    match op {
-        "+" => Ok(Rc::new(move |old| a.get(old) + b.get(old))),
-        "*" => Ok(Rc::new(move |old| a.get(old) * b.get(old))),
-        _ => panic!("Unknown operation"),
+impl FromStr for Expr {
+    type Err = ExprError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = ExprParser {
+            chars: s.char_indices().peekable(),
+        };
+        let expr = parser.expression()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some((column, c)) => Err(ExprParser::unexpected(column, c)),
+        }
     }
 }
 
-fn round_recap(round: usize, monkeys: &[Monkey]) {
-    println!("After round {}:", round);
+/// The items held by every monkey, like in the puzzle
+fn round_recap<W: Display>(round: usize, monkeys: &[Monkey<W>]) -> String {
+    let mut recap = format!(
+        "After round {}, the monkeys are holding items with these worry levels:\n",
+        round
+    );
     for (index, monkey) in monkeys.iter().enumerate() {
-        println!("Monkey {}: {:?}", index, monkey.items);
+        recap += &format!("Monkey {}: {}\n", index, monkey.items.iter().join(", "));
+    }
+    recap
+}
+
+#[cfg(test)]
+mod expr_tests {
+    use super::*;
+
+    fn parse(s: &str) -> Expr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn puzzle_operations() {
+        assert_eq!(parse("old * 19").eval(&79u64).unwrap(), 1501);
+        assert_eq!(parse("old + 6").eval(&54u64).unwrap(), 60);
+        assert_eq!(parse("old * old").eval(&79u64).unwrap(), 6241);
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        assert_eq!(parse("old + 2 * 3").eval(&1u64).unwrap(), 7);
+        assert_eq!(parse("(old + 2) * 3").eval(&1u64).unwrap(), 9);
+        assert_eq!(parse("old - 4 - 3").eval(&10u64).unwrap(), 3);
+        assert_eq!(parse("old - (4 - 3)").eval(&10u64).unwrap(), 9);
+        assert_eq!(parse("old / 2 / 2").eval(&100u64).unwrap(), 25);
+        assert_eq!(parse("((old))*(old-1)/2").eval(&5u64).unwrap(), 10);
+        // the same operation works on any integer type
+        assert_eq!(
+            parse("old * old").eval(&(u64::MAX as u128)).unwrap(),
+            (u64::MAX as u128).pow(2)
+        );
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for s in [
+            "old * 19",
+            "old + 2 * 3",
+            "(old + 2) * 3",
+            "old - (4 - 3)",
+            "old / (2 * old)",
+            "(old - 1) * (old + 1) - old / 3",
+        ] {
+            let expr = parse(s);
+            assert_eq!(expr.to_string(), s);
+            assert_eq!(parse(&expr.to_string()), expr);
+        }
+        assert_eq!(parse("( old*old )").to_string(), "old * old");
+    }
+
+    #[test]
+    fn bad_expressions() {
+        let unexpected = |column, found: &str| {
+            Err(ExprError::Unexpected {
+                column,
+                found: found.to_string(),
+            })
+        };
+        assert_eq!("old ^ 2".parse::<Expr>(), unexpected(5, "^"));
+        assert_eq!("old * new".parse::<Expr>(), unexpected(7, "new"));
+        assert_eq!("(old + 1".parse::<Expr>(), Err(ExprError::UnexpectedEnd));
+        assert_eq!("old +".parse::<Expr>(), Err(ExprError::UnexpectedEnd));
+        assert_eq!("old 2".parse::<Expr>(), unexpected(5, "2"));
+        assert_eq!("".parse::<Expr>(), Err(ExprError::UnexpectedEnd));
+    }
+}
+
+#[cfg(test)]
+mod worry_tests {
+    use super::*;
+
+    fn example_monkeys() -> Vec<Monkey> {
+        parse_monkeys(include_str!("../inputs/example11.txt")).unwrap()
+    }
+
+    #[test]
+    fn overflow_detection() -> aoc22::MyResult<()> {
+        let monkeys = example_monkeys();
+        let error = monkey_business(monkeys.clone(), 10000, Relief::None).unwrap_err();
+        assert_eq!(error.downcast_ref(), Some(&WorryError::Overflow));
+        let wide: Vec<Monkey<u128>> = monkeys
+            .into_iter()
+            .map(|m| m.map_items(|item| Ok::<_, WorryError>(item as u128)))
+            .collect::<Result<_, _>>()?;
+        // u128 lasts a bit longer, but not much with an `old * old` monkey
+        assert!(monkey_business(wide.clone(), 4, Relief::None).is_ok());
+        assert!(monkey_business(wide, 10000, Relief::None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn residues_match_integers() -> aoc22::MyResult<()> {
+        let mut monkeys = example_monkeys();
+        for rounds in [1, 3, 4] {
+            let wide: Vec<Monkey<u128>> = monkeys
+                .iter()
+                .cloned()
+                .map(|m| m.map_items(|item| Ok::<_, WorryError>(item as u128)))
+                .collect::<Result<_, _>>()?;
+            assert_eq!(
+                monkey_business(with_residues(monkeys.clone())?, rounds, Relief::None)?,
+                monkey_business(wide, rounds, Relief::None)?
+            );
+        }
+        // divisors don't need to be prime
+        for (monkey, divisor) in monkeys.iter_mut().zip([4, 6, 9, 10]) {
+            monkey.divisor = divisor;
+        }
+        assert_eq!(
+            monkey_business(with_residues(monkeys.clone())?, 3, Relief::None)?,
+            monkey_business(monkeys, 3, Relief::None)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn residue_arithmetic() -> Result<(), WorryError> {
+        let moduli: Arc<[u64]> = Arc::new([7, 10, 13]);
+        let a = Residues::new(1234, moduli.clone())?;
+        let b = Residues::new(99, moduli.clone())?;
+        assert_eq!(
+            a.apply(BinOp::Add, &b)?,
+            Residues::new(1333, moduli.clone())?
+        );
+        assert_eq!(
+            a.apply(BinOp::Sub, &b)?,
+            Residues::new(1135, moduli.clone())?
+        );
+        assert_eq!(
+            a.apply(BinOp::Mul, &b)?,
+            Residues::new(122166, moduli.clone())?
+        );
+        assert!(a.apply(BinOp::Div, &b).is_err());
+        assert_eq!(a.is_divisible_by(13), Ok(false));
+        assert_eq!(b.constant(91).is_divisible_by(13), Ok(true));
+        assert!(a.is_divisible_by(3).is_err());
+        assert!(Residues::new(1, Arc::new([0])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn example_part2_without_lcm() -> aoc22::MyResult<()> {
+        let monkeys = with_residues(example_monkeys())?;
+        assert_eq!(monkey_business(monkeys, 10000, Relief::None)?, 2713310158);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod cycle_tests {
+    use super::*;
+
+    fn example_monkeys() -> Vec<Monkey<Residues>> {
+        with_residues(parse_monkeys(include_str!("../inputs/example11.txt")).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn matches_simulation() -> Result<(), WorryError> {
+        let mut monkeys = example_monkeys();
+        let start = monkeys.clone();
+        for round in 1..=2000 {
+            play_round(&mut monkeys, round as usize, Relief::None, &mut ())?;
+            if round % 97 == 0 || round == 1 || round == 1000 {
+                let simulated: Vec<u128> = monkeys.iter().map(|m| m.inspections as u128).collect();
+                assert_eq!(extrapolate_inspections(&start, round)?, simulated);
+            }
+        }
+        assert_eq!(extrapolate_inspections(&start, 0)?, vec![0; 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn billions_of_rounds() -> aoc22::MyResult<()> {
+        let monkeys = example_monkeys();
+        let inspections = extrapolate_inspections(&monkeys, 10000)?;
+        assert_eq!(inspections, [52166, 47830, 1938, 52013]);
+        assert_eq!(business_of(&inspections)?, 2713310158);
+
+        let huge = extrapolate_inspections(&monkeys, 1_000_000_000_000)?;
+        // every item ends up looping, so the counts grow linearly
+        let twice = extrapolate_inspections(&monkeys, 2_000_000_000_000)?;
+        for (once, twice) in huge.iter().zip(&twice) {
+            assert!(once.abs_diff(twice / 2) < 1000);
+        }
+        assert!(business_of(&huge)? > u64::MAX as u128);
+
+        let most = extrapolate_inspections(&monkeys, u64::MAX)?;
+        // the two busiest monkeys each inspect more than 2^64 items
+        assert!(
+            most.iter()
+                .filter(|&&count| count > u64::MAX as u128)
+                .count()
+                >= 2
+        );
+        assert!(business_of(&most).is_err());
+        assert!(business_of(&most[..1]).is_err());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod event_tests {
+    use super::*;
+
+    fn example_monkeys() -> Vec<Monkey> {
+        parse_monkeys(include_str!("../inputs/example11.txt")).unwrap()
+    }
+
+    #[test]
+    fn narration_of_the_first_round() -> Result<(), WorryError> {
+        let mut monkeys = example_monkeys();
+        let mut log = EventLog::default();
+        play_round(&mut monkeys, 1, Relief::DivideBy(3), &mut log)?;
+        let narration = log.narrate(&monkeys, Relief::DivideBy(3));
+        assert!(narration.starts_with(
+            "\
+Monkey 0:
+  Monkey inspects an item with a worry level of 79.
+    Worry level is multiplied by 19 to 1501.
+    Monkey gets bored with item. Worry level is divided by 3 to 500.
+    Current worry level is not divisible by 23.
+    Item with worry level 500 is thrown to monkey 3.
+  Monkey inspects an item with a worry level of 98.
+    Worry level is multiplied by 19 to 1862.
+    Monkey gets bored with item. Worry level is divided by 3 to 620.
+    Current worry level is not divisible by 23.
+    Item with worry level 620 is thrown to monkey 3.
+Monkey 1:
+  Monkey inspects an item with a worry level of 54.
+    Worry level increases by 6 to 60.
+    Monkey gets bored with item. Worry level is divided by 3 to 20.
+    Current worry level is not divisible by 19.
+    Item with worry level 20 is thrown to monkey 0.
+"
+        ));
+        assert!(narration.contains(
+            "\
+Monkey 2:
+  Monkey inspects an item with a worry level of 79.
+    Worry level is multiplied by itself to 6241.
+    Monkey gets bored with item. Worry level is divided by 3 to 2080.
+    Current worry level is divisible by 13.
+    Item with worry level 2080 is thrown to monkey 1.
+"
+        ));
+        assert!(narration.ends_with(
+            "\
+  Monkey inspects an item with a worry level of 3136.
+    Worry level increases by 3 to 3139.
+    Monkey gets bored with item. Worry level is divided by 3 to 1046.
+    Current worry level is not divisible by 17.
+    Item with worry level 1046 is thrown to monkey 1.
+"
+        ));
+        assert_eq!(
+            round_recap(1, &monkeys),
+            "\
+After round 1, the monkeys are holding items with these worry levels:
+Monkey 0: 20, 23, 27, 26
+Monkey 1: 2080, 25, 167, 207, 401, 1046
+Monkey 2: \nMonkey 3: \n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn filters() -> Result<(), WorryError> {
+        let mut monkeys = example_monkeys();
+        let mut log = EventLog {
+            filter: EventFilter {
+                monkeys: Some(vec![3]),
+                rounds: 2..=3,
+            },
+            events: Vec::new(),
+        };
+        for round in 1..=20 {
+            play_round(&mut monkeys, round, Relief::DivideBy(3), &mut log)?;
+        }
+        assert!(log
+            .events
+            .iter()
+            .all(|e| e.monkey == 3 && (2..=3).contains(&e.round)));
+        let inspections = |round| {
+            log.events
+                .iter()
+                .filter(|e| e.round == round && matches!(e.kind, EventKind::Inspect { .. }))
+                .count()
+        };
+        // monkey 3 inspects every item thrown to it during the round, 20 * 19 / 3 being the first
+        assert_eq!(inspections(2), 5);
+        assert_eq!(inspections(3), 5);
+        assert_eq!(
+            log.events[0],
+            Event {
+                round: 2,
+                monkey: 3,
+                kind: EventKind::Catch { worry: 126 }
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn json_lines() -> aoc22::MyResult<()> {
+        let mut monkeys = with_residues(example_monkeys())?;
+        let filter = EventFilter::from_args(&["--monkeys".to_string(), "0".to_string()])?;
+        let mut sink = JsonLines::new(Vec::new(), filter);
+        play_round(&mut monkeys, 1, Relief::None, &mut sink)?;
+        let json = String::from_utf8(sink.finish()?)?;
+        let lines: Vec<&str> = json.lines().collect();
+        assert_eq!(lines[0], r#"{"round":1,"monkey":0,"event":"turn"}"#);
+        assert_eq!(
+            lines[1],
+            r#"{"round":1,"monkey":0,"event":"inspect","worry":"10 mod 23, 3 mod 19, 1 mod 13, 11 mod 17"}"#
+        );
+        assert_eq!(
+            lines[3],
+            r#"{"round":1,"monkey":0,"event":"test","divisor":23,"divisible":false}"#
+        );
+        assert!(lines[4].contains(r#""event":"throw""#) && lines[4].ends_with(r#""target":3}"#));
+
+        let rounds = |s: &str| EventFilter::from_args(&["--rounds".to_string(), s.to_string()]);
+        assert_eq!(rounds("3-5")?.rounds, 3..=5);
+        assert_eq!(rounds("7")?.rounds, 7..=7);
+        assert!(rounds("a-b").is_err());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod parser_tests {
+    use super::*;
+
+    const MONKEY: &str = "\
+Monkey 0:
+  Starting items: 79, 98
+  Operation: new = old * 19
+  Test: divisible by 23
+    If true: throw to monkey 2
+    If false: throw to monkey 3
+";
+
+    #[test]
+    fn whitespace() {
+        let expected: Monkey = MONKEY.parse().unwrap();
+        assert_eq!(expected.items, [79, 98]);
+        assert_eq!(expected.target, (2, 3));
+        let crlf = MONKEY.replace('\n', "\r\n");
+        assert_eq!(crlf.parse(), Ok(expected.clone()));
+        let flat = "\n\nMonkey  0:\nStarting items:79,98\n\n\tOperation:new=old*19\n \
+                    Test:  divisible  by 23\nIf true: throw to monkey 2\nIf false: throw to monkey 3";
+        assert_eq!(flat.parse(), Ok(expected));
+
+        let example = include_str!("../inputs/example11.txt");
+        let monkeys = parse_monkeys(example).unwrap();
+        assert_eq!(monkeys.len(), 4);
+        assert_eq!(
+            parse_monkeys(&example.replace('\n', "\r\n")),
+            Ok(monkeys.clone())
+        );
+        let squashed = example.replace("\n\n", "\n");
+        assert_eq!(parse_monkeys(&squashed), Ok(monkeys));
+        assert_eq!(parse_monkeys(""), Ok(vec![]));
+        let no_items = MONKEY.replace("79, 98", "");
+        assert!(no_items.parse::<Monkey>().unwrap().items.is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        let parse = |s: String| s.parse::<Monkey>();
+        assert_eq!(
+            parse(MONKEY.replace("79, 98", "79, x")),
+            Err(MonkeyError::BadNumber {
+                line: 2,
+                found: "x".to_string()
+            })
+        );
+        assert_eq!(
+            parse(MONKEY.replace("Test", "Check")),
+            Err(MonkeyError::Malformed {
+                line: 4,
+                expected: "Test",
+                found: "Check: divisible by 23".to_string()
+            })
+        );
+        assert_eq!(
+            parse(MONKEY.replace("throw to monkey 3", "drop it")),
+            Err(MonkeyError::Malformed {
+                line: 6,
+                expected: "throw to monkey",
+                found: "drop it".to_string()
+            })
+        );
+        assert_eq!(
+            parse(MONKEY.replace("old * 19", "old ^ 2")),
+            Err(MonkeyError::BadOperation {
+                line: 3,
+                error: ExprError::Unexpected {
+                    column: 5,
+                    found: "^".to_string()
+                }
+            })
+        );
+        assert_eq!(
+            parse(MONKEY.replace("new =", "old =")),
+            Err(MonkeyError::Malformed {
+                line: 3,
+                expected: "new = <expression>",
+                found: "old = old * 19".to_string()
+            })
+        );
+        assert_eq!(
+            parse(MONKEY.lines().take(4).join("\n")),
+            Err(MonkeyError::MissingLine {
+                expected: "If true"
+            })
+        );
+        assert_eq!(
+            parse(MONKEY.to_string() + "Monkey 1:"),
+            Err(MonkeyError::Malformed {
+                line: 7,
+                expected: "the end of the notes",
+                found: "Monkey 1:".to_string()
+            })
+        );
+        assert_eq!(
+            parse(MONKEY.replace("Monkey 0:", "Monkey zero:")),
+            Err(MonkeyError::BadNumber {
+                line: 1,
+                found: "zero".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn numbering_and_targets() {
+        let example = include_str!("../inputs/example11.txt");
+        assert_eq!(
+            parse_monkeys(&example.replace("Monkey 2:", "Monkey 3:")),
+            Err(MonkeyError::OutOfSequence {
+                line: 15,
+                expected: 2,
+                found: "Monkey 3:".to_string()
+            })
+        );
+        assert_eq!(
+            parse_monkeys(&example.replace("Monkey 2:", "Monkey:")),
+            Err(MonkeyError::Malformed {
+                line: 15,
+                expected: "\"Monkey <n>:\"",
+                found: "Monkey:".to_string()
+            })
+        );
+        assert_eq!(
+            parse_monkeys(MONKEY),
+            Err(MonkeyError::UnknownTarget {
+                line: 1,
+                target: 2,
+                monkeys: 1
+            })
+        );
+        assert_eq!(
+            parse_monkeys(
+                &example.replace("If false: throw to monkey 0", "If false: throw to monkey 1")
+            ),
+            Err(MonkeyError::ThrowsToItself { line: 8, monkey: 1 })
+        );
+        let selfish = MONKEY
+            .replace("monkey 2", "monkey 0")
+            .replace("monkey 3", "monkey 0");
+        assert_eq!(
+            parse_monkeys(&selfish),
+            Err(MonkeyError::ThrowsToItself { line: 1, monkey: 0 })
+        );
+        let error = parse_monkeys(MONKEY).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "line 1: this monkey throws to monkey 2, but there are only 1 monkeys"
+        );
     }
-    println!();
 }
@@ -8,17 +8,12 @@ aoc22::test_with_example!(day12, "../inputs/example12.txt", 31, 29);
 
 pub fn day12(input: &str) -> aoc22::MyResult<(u32, u32)> {
     let (elevation_map, start, end) = parse_elevations(input)?;
+    let distances = DistanceMap::new(&elevation_map, [end])?;
 
-    let part1 = shortest_path(&elevation_map, start, end).ok_or("Shortest path not found")?;
-
-    let lowest_points = elevation_map
-        .indexed_iter()
-        .filter(|(_, &elevation)| elevation == 'a')
-        .map(|(coord, _)| coord);
-    let part2 = lowest_points
-        .filter_map(|coord| shortest_path(&elevation_map, coord, end))
-        .min()
-        .ok_or("Couldn't reach end")?;
+    let part1 = distances
+        .distance_from(start)
+        .ok_or("Shortest path not found")?;
+    let part2 = distances.closest('a').ok_or("Couldn't reach end")?.1;
 
     Ok((part1, part2))
 }
@@ -48,44 +43,124 @@ fn parse_elevations(input: &str) -> aoc22::MyResult<(Array2<char>, Coord, Coord)
     Ok((elevation_map, start, end))
 }
 
-fn shortest_path(elevation_map: &Array2<char>, start: Coord, end: Coord) -> Option<u32> {
-    let mut distances: Array2<Option<u32>> = Array2::from_elem(elevation_map.raw_dim(), None);
-
-    let mut to_process: HashSet<Coord> = HashSet::new();
-    to_process.insert(start);
-    let mut i = 0u32;
-    while !to_process.is_empty() {
-        to_process.iter().for_each(|&coord| {
-            distances[coord] = Some(i);
-        });
-        i += 1;
-        to_process = to_process
-            .into_iter()
-            .flat_map(steps_from)
-            .filter(|&(from, to)| reachable(from, to, elevation_map) && distances[to].is_none())
-            .map(|(_, to)| to)
-            .collect();
+/// Length of the shortest path from every cell to the nearest of some goals,
+/// found with a single search going down from the goals
+struct DistanceMap<'a> {
+    elevation_map: &'a Array2<char>,
+    /// None for the cells that can't reach any goal
+    distances: Array2<Option<u32>>,
+}
+
+impl<'a> DistanceMap<'a> {
+    fn new(
+        elevation_map: &'a Array2<char>,
+        goals: impl IntoIterator<Item = Coord>,
+    ) -> aoc22::MyResult<Self> {
+        let mut distances: Array2<Option<u32>> = Array2::from_elem(elevation_map.raw_dim(), None);
+
+        let mut to_process: HashSet<Coord> = goals.into_iter().collect();
+        if let Some(goal) = to_process
+            .iter()
+            .find(|&&goal| elevation_map.get(goal).is_none())
+        {
+            return Err(format!("Goal {:?} is outside of the map", goal).into());
+        }
+        let mut i = 0u32;
+        while !to_process.is_empty() {
+            to_process.iter().for_each(|&coord| {
+                distances[coord] = Some(i);
+            });
+            i += 1;
+            // the cells from which one of those can be reached in a step
+            to_process = to_process
+                .into_iter()
+                .flat_map(|to| neighbours(to).map(|from| (from, to)))
+                .filter(|&(from, to)| {
+                    reachable(from, to, elevation_map) && distances[from].is_none()
+                })
+                .map(|(from, _)| from)
+                .collect();
+        }
+        Ok(DistanceMap {
+            elevation_map,
+            distances,
+        })
+    }
+
+    fn distance_from(&self, coord: Coord) -> Option<u32> {
+        self.distances.get(coord).copied().flatten()
+    }
+
+    /// The cell of the given elevation closest to a goal, and its distance
+    fn closest(&self, elevation: char) -> Option<(Coord, u32)> {
+        self.distances
+            .indexed_iter()
+            .filter(|&(coord, _)| self.elevation_map[coord] == elevation)
+            .filter_map(|(coord, &distance)| Some((coord, distance?)))
+            .min_by_key(|&(_, distance)| distance)
     }
-    distances[end]
 }
 
-fn reachable(
-    from: (usize, usize),
-    (to_x, to_y): (usize, usize),
-    elevation_map: &Array2<char>,
-) -> bool {
-    if to_x >= elevation_map.shape()[0] || to_y >= elevation_map.shape()[1] {
-        false
-    } else {
-        elevation_map[(to_x, to_y)] as u32 <= elevation_map[from] as u32 + 1
+fn reachable(from: Coord, to: Coord, elevation_map: &Array2<char>) -> bool {
+    match (elevation_map.get(from), elevation_map.get(to)) {
+        (Some(&from), Some(&to)) => to as u32 <= from as u32 + 1,
+        _ => false,
     }
 }
 
-fn steps_from((x, y): Coord) -> [(Coord, Coord); 4] {
+fn neighbours((x, y): Coord) -> [Coord; 4] {
     [
-        ((x, y), (x + 1, y)),
-        ((x, y), (if x > 0 { x - 1 } else { usize::MAX }, y)),
-        ((x, y), (x, y + 1)),
-        ((x, y), (x, if y > 0 { y - 1 } else { usize::MAX })),
+        (x + 1, y),
+        (if x > 0 { x - 1 } else { usize::MAX }, y),
+        (x, y + 1),
+        (x, if y > 0 { y - 1 } else { usize::MAX }),
     ]
 }
+
+#[cfg(test)]
+mod distance_tests {
+    use super::*;
+
+    #[test]
+    fn distances_from_every_cell() -> aoc22::MyResult<()> {
+        let (elevation_map, start, end) =
+            parse_elevations(include_str!("../inputs/example12.txt"))?;
+        let distances = DistanceMap::new(&elevation_map, [end])?;
+        assert_eq!(distances.distance_from(end), Some(0));
+        assert_eq!(distances.distance_from(start), Some(31));
+        // right next to the goal, but too low to climb to it, so it goes around through x, x, y and z
+        assert_eq!(elevation_map[(2, 6)], 'x');
+        assert_eq!(distances.distance_from((2, 6)), Some(5));
+        // outside of the map
+        assert_eq!(distances.distance_from((5, 0)), None);
+        assert_eq!(distances.closest('a'), Some(((4, 0), 29)));
+        assert_eq!(distances.closest('z'), Some((end, 0)));
+        assert_eq!(distances.closest('A'), None);
+        Ok(())
+    }
+
+    #[test]
+    fn unreachable_cells_and_several_goals() -> aoc22::MyResult<()> {
+        let elevation_map = Array2::from_shape_vec((2, 4), "abcdaaaz".chars().collect()).unwrap();
+        let distances = DistanceMap::new(&elevation_map, [(0, 3)])?;
+        assert_eq!(distances.distance_from((1, 0)), Some(4));
+        assert_eq!(distances.closest('z'), Some(((1, 3), 1)));
+        // nothing climbs from d or a to z
+        let distances = DistanceMap::new(&elevation_map, [(1, 3)])?;
+        assert_eq!(distances.distance_from((0, 3)), None);
+        assert_eq!(distances.closest('a'), None);
+        let distances = DistanceMap::new(&elevation_map, [(0, 3), (1, 0)])?;
+        assert_eq!(distances.closest('a'), Some(((1, 0), 0)));
+        assert_eq!(distances.distance_from((1, 2)), Some(2));
+        assert_eq!(distances.distance_from((0, 2)), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn goals_outside_of_the_map() {
+        let elevation_map = Array2::from_shape_vec((2, 4), "abcdaaaz".chars().collect()).unwrap();
+        assert!(DistanceMap::new(&elevation_map, [(2, 0)]).is_err());
+        assert!(DistanceMap::new(&elevation_map, [(0, 3), (0, 4)]).is_err());
+        assert!(DistanceMap::new(&elevation_map, [(1, 3)]).is_ok());
+    }
+}
@@ -2,6 +2,8 @@ use std::{collections::HashSet, ops::RangeInclusive};
 
 use itertools::Itertools;
 
+use aoc22::interval_set::IntervalSet;
+
 aoc22::main!(day15_input, "../inputs/input15.txt");
 
 aoc22::test_with_example!(day15_example, "../inputs/example15.txt", 26, 56000011);
@@ -101,8 +103,8 @@ fn parse_sensors(input: &str) -> aoc22::MyResult<Vec<Sensor>> {
 fn find_uncovered_tile(area_size: isize, sensors: &Vec<Sensor>) -> Option<Coord> {
     for y in 0..=area_size {
         let not_covered = remove_covered_ranges(area_size, y, sensors);
-        if let Some(r) = not_covered.first() {
-            return Some((*r.start(), y));
+        if let Some(x) = not_covered.min() {
+            return Some((x, y));
         }
         if y % 100000 == 0 {
             println!("{}", y);
@@ -111,49 +113,12 @@ fn find_uncovered_tile(area_size: isize, sensors: &Vec<Sensor>) -> Option<Coord>
     None
 }
 
-fn remove_covered_ranges(
-    area_size: isize,
-    y: isize,
-    sensors: &Vec<Sensor>,
-) -> Vec<RangeInclusive<isize>> {
-    let mut row = vec![(0..=area_size)];
+fn remove_covered_ranges(area_size: isize, y: isize, sensors: &Vec<Sensor>) -> IntervalSet<isize> {
+    let mut row = IntervalSet::from(0..=area_size);
     for s in sensors {
         if let Some(range) = s.range_for_row(y) {
-            remove_range(&mut row, range);
+            row.remove(range);
         }
     }
     row
 }
-
-fn remove_range(row: &mut Vec<RangeInclusive<isize>>, range: RangeInclusive<isize>) {
-    if range.is_empty() {
-        return;
-    }
-    let start = *range.start();
-    let end = *range.end();
-    let mut i = 0usize;
-    while i < row.len() {
-        let r = &mut row[i];
-        let r_start = *r.start();
-        let r_end = *r.end();
-        if r_start >= start && r_end <= end {
-            row.remove(i);
-            continue; // don't increase i
-        } else if start <= r_end {
-            if start > r_start && end > r_end {
-                *r = r_start..=(start - 1);
-            } else {
-                if start > r_start {
-                    *r = r_start..=(start - 1);
-                    if end < r_end {
-                        row.insert(i + 1, (end + 1)..=r_end);
-                    }
-                } else if end >= r_start {
-                    *r = (end + 1)..=r_end;
-                }
-                break;
-            }
-        }
-        i += 1;
-    }
-}
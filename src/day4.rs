@@ -1,5 +1,7 @@
 use std::ops::RangeInclusive;
 
+use aoc22::interval_set::IntervalSet;
+
 fn main() -> aoc22::MyResult<()> {
     day4_1()?;
     day4_2()?;
@@ -29,15 +31,12 @@ fn get_pairs_of_sections(
 fn day4_1() -> aoc22::MyResult<()> {
     let pairs_of_sections = get_pairs_of_sections()?;
     let res = pairs_of_sections
-        .filter_map(|(range1, range2)| {
-            let fully_contains = |r1: &RangeInclusive<u32>, r2: &RangeInclusive<u32>| {
-                r1.start() <= r2.start() && r1.end() >= r2.end()
-            };
-            if fully_contains(&range1, &range2) || fully_contains(&range2, &range1) {
-                Some(1)
-            } else {
-                None
-            }
+        .filter(|(range1, range2)| {
+            let (set1, set2) = (
+                IntervalSet::from(range1.clone()),
+                IntervalSet::from(range2.clone()),
+            );
+            set1.is_superset(&set2) || set2.is_superset(&set1)
         })
         .count();
     println!("{}", res);
@@ -48,16 +47,7 @@ fn day4_1() -> aoc22::MyResult<()> {
 fn day4_2() -> aoc22::MyResult<()> {
     let pairs_of_sections = get_pairs_of_sections()?;
     let res = pairs_of_sections
-        .filter_map(|(range1, range2)| {
-            let overlap = |r1: &RangeInclusive<u32>, r2: &RangeInclusive<u32>| {
-                r1.start() <= r2.start() && r1.end() >= r2.start()
-            };
-            if overlap(&range1, &range2) || overlap(&range2, &range1) {
-                Some(1)
-            } else {
-                None
-            }
-        })
+        .filter(|(range1, range2)| IntervalSet::from(range1.clone()).overlaps(range2))
         .count();
     println!("{}", res);
 
@@ -1,15 +1,29 @@
-use std::{fmt::Display, str::FromStr};
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{BufRead, Write},
+    str::FromStr,
+    thread,
+    time::Duration,
+};
 
 use itertools::Itertools;
 
 fn main() -> aoc22::MyResult<()> {
+    // a crane model can be given on the command line, optionally followed by a replay mode
+    // e.g. `cargo run --bin day5 -- limited:3 --animate 50`
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let [model, replay_args @ ..] = &args[..] {
+        let replay = Replay::from_args(replay_args)?;
+        return crate_mover(crate_mover_by_name(model)?.as_ref(), replay);
+    }
     day5_1()?;
     day5_2()?;
 
     Ok(())
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 struct Instruction {
     count: usize,
     source: usize,
@@ -17,15 +31,22 @@ struct Instruction {
 }
 
 impl FromStr for Instruction {
-    type Err = std::num::ParseIntError;
+    type Err = Box<dyn Error>;
 
     fn from_str(str: &str) -> Result<Self, Self::Err> {
-        let words = str.split(' ').collect_vec();
-        Ok(Instruction {
-            count: words[1].parse()?,
-            source: words[3].parse::<usize>()? - 1usize,
-            dest: words[5].parse::<usize>()? - 1usize,
-        })
+        let stack_index = |s: &str| -> Result<usize, Self::Err> {
+            s.parse::<usize>()?
+                .checked_sub(1)
+                .ok_or_else(|| "Stack numbers start at 1".into())
+        };
+        match str.split_whitespace().collect::<Vec<_>>()[..] {
+            ["move", count, "from", source, "to", dest] => Ok(Instruction {
+                count: count.parse()?,
+                source: stack_index(source)?,
+                dest: stack_index(dest)?,
+            }),
+            _ => Err(format!("Can't parse instruction : {:?}", str).into()),
+        }
     }
 }
 
@@ -43,53 +64,207 @@ impl Display for Instruction {
 
 type Stack = Vec<char>; //< crates, top to bottom
 
-#[derive(Debug, Default)]
+/// Reason why an instruction can't be applied to some stacks
+#[derive(Debug, PartialEq)]
+enum InvalidInstruction {
+    NoSuchStack(usize),
+    NotEnoughCrates { available: usize, requested: usize },
+}
+
+impl Display for InvalidInstruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoSuchStack(stack) => write!(f, "stack {} doesn't exist", stack + 1),
+            Self::NotEnoughCrates {
+                available,
+                requested,
+            } => write!(
+                f,
+                "can't move {} crates from a stack of {}",
+                requested, available
+            ),
+        }
+    }
+}
+
+/// Error raised while executing a procedure, with the state of the stacks when it failed
+#[derive(Debug)]
+struct CraneError {
+    /// 1-based position of the instruction in the procedure
+    instruction_number: usize,
+    instruction: Instruction,
+    stacks: Stacks,
+    reason: InvalidInstruction,
+}
+
+impl Display for CraneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Instruction {} \"{}\" failed: {}",
+            self.instruction_number, self.instruction, self.reason
+        )?;
+        write!(f, "{}", self.stacks)
+    }
+}
+
+impl Error for CraneError {}
+
+type CraneResult = Result<(), InvalidInstruction>;
+
+#[derive(Debug, Default, Clone, PartialEq)]
 struct Stacks {
     stacks: Vec<Stack>,
 }
 
 impl Stacks {
     /// parse the first part of the input as a Stacks object
-    fn parse(lines: Vec<String>) -> aoc22::MyResult<Stacks> {
-        let mut stacks = Stacks::default();
-        stacks.stacks.resize(lines[0].len() / 4 + 1, Stack::new());
-        for l in lines.into_iter().rev() {
-            for stack in 0..stacks.stacks.len() {
-                let c = l.chars().nth(4 * stack + 1).ok_or("Bad stacks input")?;
-                if !c.is_numeric() && !c.is_whitespace() {
-                    stacks.stacks[stack].push(c);
+    fn parse(lines: &[&str]) -> aoc22::MyResult<Stacks> {
+        let (labels, rows) = lines.split_last().ok_or("Missing stacks drawing")?;
+        for (i, label) in labels.split_whitespace().enumerate() {
+            if label.parse::<usize>()? != i + 1 {
+                return Err(format!("Unexpected stack label {}", label).into());
+            }
+        }
+        let mut stacks = Stacks {
+            stacks: vec![Stack::new(); labels.split_whitespace().count()],
+        };
+        for (height, row) in rows.iter().rev().enumerate() {
+            let cells = row.chars().collect_vec();
+            for (stack_index, cell) in cells.chunks(4).enumerate() {
+                let c = match cell {
+                    ['[', c, ']'] | ['[', c, ']', ' '] => *c,
+                    _ if cell.iter().all(|c| *c == ' ') => continue,
+                    _ => return Err(format!("Bad stacks input: {:?}", row).into()),
+                };
+                let stack = stacks
+                    .stacks
+                    .get_mut(stack_index)
+                    .ok_or_else(|| format!("Crate outside of the stacks: {:?}", row))?;
+                if stack.len() != height {
+                    return Err(format!("Floating crate in stack {}", stack_index + 1).into());
                 }
+                stack.push(c);
             }
         }
         Ok(stacks)
     }
 
+    /// Write the stacks exactly as they are drawn in the puzzle input
+    fn fmt_drawing(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let max_len = self.stacks.iter().map(Vec::len).max().unwrap_or(0);
+        for height in (0..max_len).rev() {
+            let row = self
+                .stacks
+                .iter()
+                .map(|stack| match stack.get(height) {
+                    Some(c) => format!("[{}]", c),
+                    None => String::from("   "),
+                })
+                .join(" ");
+            writeln!(f, "{}", row)?;
+        }
+        let labels = (1..=self.stacks.len())
+            .map(|label| format!("{:^3}", label))
+            .join(" ");
+        writeln!(f, "{}", labels)
+    }
+
     /// Get the top of the stacks as a String
     fn top(&self) -> String {
         self.stacks.iter().filter_map(|s| s.last()).collect()
     }
 
-    /// Apply a given instruction to the stacks using CrateMover 9000
-    fn apply_9000(&mut self, inst: Instruction) -> aoc22::MyResult<()> {
-        for _ in 0..inst.count {
-            let a = self.stacks[inst.source].pop().ok_or(":(")?;
-            self.stacks[inst.dest].push(a);
+    /// Check that the given instruction can be applied to the stacks
+    fn validate(&self, inst: &Instruction) -> CraneResult {
+        let stack = |index| {
+            self.stacks
+                .get(index)
+                .ok_or(InvalidInstruction::NoSuchStack(index))
+        };
+        let available = stack(inst.source)?.len();
+        stack(inst.dest)?;
+        if inst.count > available {
+            return Err(InvalidInstruction::NotEnoughCrates {
+                available,
+                requested: inst.count,
+            });
         }
         Ok(())
     }
 
-    /// Apply a given instruction to the stacks using CrateMover 9001
-    fn apply_9001(&mut self, inst: Instruction) -> aoc22::MyResult<()> {
+    /// Apply a given instruction to the stacks using the given crane model
+    fn apply(&mut self, mover: &dyn CrateMover, inst: &Instruction) -> CraneResult {
+        self.validate(inst)?;
         let src = &mut self.stacks[inst.source];
-        let top_crates = src.split_off(src.len() - inst.count);
-        self.stacks[inst.dest].extend(top_crates);
+        let lifted = src.split_off(src.len() - inst.count);
+        self.stacks[inst.dest].extend(mover.arrange(lifted));
         Ok(())
     }
-}
 
-impl Display for Stacks {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let max_len = self.stacks.iter().map(Vec::len).max().unwrap();
+    /// Apply the instruction at the given index of a procedure
+    fn apply_step(
+        &mut self,
+        mover: &dyn CrateMover,
+        index: usize,
+        inst: &Instruction,
+    ) -> Result<(), CraneError> {
+        self.apply(mover, inst).map_err(|reason| CraneError {
+            instruction_number: index + 1,
+            instruction: inst.clone(),
+            stacks: self.clone(),
+            reason,
+        })
+    }
+
+    /// Apply every instruction of the procedure, stopping at the first invalid one
+    fn execute(
+        &mut self,
+        procedure: &[Instruction],
+        mover: &dyn CrateMover,
+    ) -> Result<(), CraneError> {
+        procedure
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, inst)| self.apply_step(mover, i, inst))
+    }
+
+    /// Check that the whole procedure can be executed, without modifying the stacks
+    fn dry_run(&self, procedure: &[Instruction], mover: &dyn CrateMover) -> Result<(), CraneError> {
+        self.clone().execute(procedure, mover)
+    }
+
+    /// Execute the procedure on a copy of the stacks, keeping every intermediate state
+    fn record(
+        &self,
+        procedure: Vec<Instruction>,
+        mover: &dyn CrateMover,
+    ) -> Result<History, CraneError> {
+        let mut stacks = self.clone();
+        let mut snapshots = vec![stacks.clone()];
+        for (i, inst) in procedure.iter().enumerate() {
+            stacks.apply_step(mover, i, inst)?;
+            snapshots.push(stacks.clone());
+        }
+        Ok(History {
+            procedure,
+            snapshots,
+        })
+    }
+
+    /// Write the stacks, highlighting the crates that were just moved by `moved`
+    fn fmt_highlighted(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        moved: Option<&Instruction>,
+    ) -> std::fmt::Result {
+        let is_moved = |stack_index: usize, height: usize| {
+            moved.is_some_and(|inst| {
+                inst.dest == stack_index
+                    && height >= self.stacks[stack_index].len().saturating_sub(inst.count)
+            })
+        };
+        let max_len = self.stacks.iter().map(Vec::len).max().unwrap_or(0);
         (0..=max_len).rev().try_for_each(|i| {
             self.stacks
                 .iter()
@@ -97,6 +272,8 @@ impl Display for Stacks {
                 .try_for_each(|(stack_index, stack)| {
                     if i == 0 {
                         write!(f, " {}  ", stack_index + 1)
+                    } else if i - 1 < stack.len() && is_moved(stack_index, i - 1) {
+                        write!(f, "{}[{}]{} ", HIGHLIGHT, stack[i - 1], RESET)
                     } else if i - 1 < stack.len() {
                         write!(f, "[{}] ", stack[i - 1])
                     } else {
@@ -109,23 +286,425 @@ impl Display for Stacks {
     }
 }
 
-fn crate_mover(
-    apply_instructions: impl Fn(&mut Stacks, Instruction) -> aoc22::MyResult<()>,
-) -> aoc22::MyResult<()> {
-    let mut lines = aoc22::read_lines("inputs/input5.txt")?;
-    let mut stacks = Stacks::parse((&mut lines).take_while(|s| !s.is_empty()).collect())?;
-    lines
-        .filter_map(|l| Instruction::from_str(&l).ok())
-        .try_for_each(|instruction| apply_instructions(&mut stacks, instruction))?;
-    println!("{}\n", stacks);
+impl Display for Stacks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_highlighted(f, None)
+    }
+}
+
+/// A whole puzzle input: the drawing of the stacks followed by the rearrangement procedure
+#[derive(Debug, Clone, PartialEq)]
+struct Puzzle {
+    stacks: Stacks,
+    procedure: Vec<Instruction>,
+}
+
+impl Puzzle {
+    fn load(path: &str) -> aoc22::MyResult<Puzzle> {
+        std::fs::read_to_string(path)?.parse()
+    }
+}
+
+impl FromStr for Puzzle {
+    type Err = Box<dyn Error>;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut lines = input.lines();
+        let drawing = (&mut lines).take_while(|l| !l.is_empty()).collect_vec();
+        let stacks = Stacks::parse(&drawing)?;
+        let procedure = lines
+            .filter(|l| !l.is_empty())
+            .map(Instruction::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Puzzle { stacks, procedure })
+    }
+}
+
+/// Writes the puzzle in the exact format of the puzzle input
+impl Display for Puzzle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.stacks.fmt_drawing(f)?;
+        writeln!(f)?;
+        self.procedure
+            .iter()
+            .try_for_each(|inst| writeln!(f, "{}", inst))
+    }
+}
+
+const HIGHLIGHT: &str = "\x1b[1;33m";
+const RESET: &str = "\x1b[0m";
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// Every state of the stacks while executing a procedure
+struct History {
+    procedure: Vec<Instruction>,
+    /// snapshots[0] is the initial state, snapshots[i] the state after the i-th instruction
+    snapshots: Vec<Stacks>,
+}
+
+/// A single state of a History, displayed with the crates moved by the last instruction highlighted
+struct Frame<'a> {
+    history: &'a History,
+    step: usize,
+}
+
+impl History {
+    fn frame(&self, step: usize) -> Frame<'_> {
+        Frame {
+            history: self,
+            step,
+        }
+    }
+
+    fn last_step(&self) -> usize {
+        self.snapshots.len() - 1
+    }
+}
+
+impl Display for Frame<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let moved = self.step.checked_sub(1).map(|i| &self.history.procedure[i]);
+        write!(f, "Step {}/{}", self.step, self.history.last_step())?;
+        if let Some(inst) = moved {
+            write!(f, ": {}", inst)?;
+        }
+        writeln!(f, "\n")?;
+        self.history.snapshots[self.step].fmt_highlighted(f, moved)
+    }
+}
+
+/// How the execution of the procedure is shown
+#[derive(Debug)]
+enum Replay {
+    /// Only show the final state
+    Off,
+    /// Play every step, waiting the given delay between steps
+    Animate(Duration),
+    /// Step forward and backward through the procedure interactively
+    Interactive,
+}
+
+impl Replay {
+    fn from_args(args: &[String]) -> aoc22::MyResult<Replay> {
+        match args {
+            [] => Ok(Replay::Off),
+            [flag] if flag == "--animate" => Ok(Replay::Animate(Duration::from_millis(100))),
+            [flag, ms] if flag == "--animate" => {
+                Ok(Replay::Animate(Duration::from_millis(ms.parse()?)))
+            }
+            [flag] if flag == "--step" => Ok(Replay::Interactive),
+            _ => Err(format!(
+                "Bad replay arguments {:?}, expected --animate [ms] or --step",
+                args
+            )
+            .into()),
+        }
+    }
+}
+
+fn animate(history: &History, delay: Duration) -> aoc22::MyResult<()> {
+    let mut stdout = std::io::stdout();
+    for step in 0..=history.last_step() {
+        write!(stdout, "{}{}", CLEAR_SCREEN, history.frame(step))?;
+        stdout.flush()?;
+        thread::sleep(delay);
+    }
+    Ok(())
+}
+
+fn step_through(history: &History) -> aoc22::MyResult<()> {
+    let mut stdout = std::io::stdout();
+    let mut input = std::io::stdin().lock().lines();
+    let mut step = 0;
+    loop {
+        write!(stdout, "{}{}", CLEAR_SCREEN, history.frame(step))?;
+        write!(
+            stdout,
+            "\n[enter] next, p: previous, <n>: go to step n, q: quit > "
+        )?;
+        stdout.flush()?;
+        let Some(command) = input.next().transpose()? else {
+            return Ok(());
+        };
+        match command.trim() {
+            "" | "n" => step = (step + 1).min(history.last_step()),
+            "p" => step = step.saturating_sub(1),
+            "q" => return Ok(()),
+            n => {
+                if let Ok(n) = n.parse::<usize>() {
+                    step = n.min(history.last_step());
+                }
+            }
+        }
+    }
+}
+
+/// A crane model, defined by the order in which it puts down the crates it lifted
+trait CrateMover {
+    /// Reorder the crates lifted from the source stack (bottom to top),
+    /// in the order they are stacked on the destination stack
+    fn arrange(&self, lifted: Stack) -> Stack;
+}
+
+/// Moves crates one at a time
+struct CrateMover9000;
+
+impl CrateMover for CrateMover9000 {
+    fn arrange(&self, mut lifted: Stack) -> Stack {
+        lifted.reverse();
+        lifted
+    }
+}
+
+/// Moves all the crates at once
+struct CrateMover9001;
+
+impl CrateMover for CrateMover9001 {
+    fn arrange(&self, lifted: Stack) -> Stack {
+        lifted
+    }
+}
+
+/// Moves at most `capacity` crates at once, starting from the top of the source stack
+struct LimitedCrateMover {
+    capacity: usize,
+}
+
+impl CrateMover for LimitedCrateMover {
+    fn arrange(&self, lifted: Stack) -> Stack {
+        lifted
+            .rchunks(self.capacity)
+            .flat_map(|batch| batch.iter().copied())
+            .collect()
+    }
+}
+
+/// Moves all the crates at once, but only every other crate ends up in reverse order
+struct AlternatingCrateMover;
+
+impl CrateMover for AlternatingCrateMover {
+    fn arrange(&self, mut lifted: Stack) -> Stack {
+        let reversed: Stack = lifted.iter().step_by(2).rev().copied().collect();
+        lifted
+            .iter_mut()
+            .step_by(2)
+            .zip(reversed)
+            .for_each(|(c, r)| *c = r);
+        lifted
+    }
+}
+
+fn crate_mover_by_name(name: &str) -> aoc22::MyResult<Box<dyn CrateMover>> {
+    match name.split_once(':') {
+        None if name == "9000" => Ok(Box::new(CrateMover9000)),
+        None if name == "9001" => Ok(Box::new(CrateMover9001)),
+        None if name == "alternating" => Ok(Box::new(AlternatingCrateMover)),
+        Some(("limited", capacity)) => match capacity.parse()? {
+            0 => Err("A crane must be able to lift at least one crate".into()),
+            capacity => Ok(Box::new(LimitedCrateMover { capacity })),
+        },
+        _ => Err(format!(
+            "Unknown crane model {:?}, expected 9000, 9001, alternating or limited:<capacity>",
+            name
+        )
+        .into()),
+    }
+}
+
+fn crate_mover(mover: &dyn CrateMover, replay: Replay) -> aoc22::MyResult<()> {
+    let Puzzle {
+        mut stacks,
+        procedure,
+    } = Puzzle::load("inputs/input5.txt")?;
+    match replay {
+        Replay::Off => {
+            stacks.dry_run(&procedure, mover)?;
+            stacks.execute(&procedure, mover)?;
+            println!("{}\n", stacks);
+        }
+        Replay::Animate(delay) => {
+            let history = stacks.record(procedure, mover)?;
+            animate(&history, delay)?;
+            stacks = history.snapshots[history.last_step()].clone();
+        }
+        Replay::Interactive => {
+            let history = stacks.record(procedure, mover)?;
+            step_through(&history)?;
+            stacks = history.snapshots[history.last_step()].clone();
+        }
+    }
     println!("{}\n", stacks.top());
     Ok(())
 }
 
 fn day5_1() -> aoc22::MyResult<()> {
-    crate_mover(Stacks::apply_9000)
+    crate_mover(&CrateMover9000, Replay::Off)
 }
 
 fn day5_2() -> aoc22::MyResult<()> {
-    crate_mover(Stacks::apply_9001)
+    crate_mover(&CrateMover9001, Replay::Off)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn example_stacks() -> Stacks {
+        Stacks {
+            stacks: vec![vec!['Z', 'N'], vec!['M', 'C', 'D'], vec!['P']],
+        }
+    }
+
+    fn procedure(lines: &[&str]) -> Vec<Instruction> {
+        lines.iter().map(|l| l.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn bad_instructions_are_rejected() {
+        for bad in [
+            "move 1 from 0 to 2",
+            "move 1 from 2",
+            "move x from 1 to 2",
+            "",
+        ] {
+            assert!(Instruction::from_str(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn execution_error_reports_position() {
+        let mut stacks = example_stacks();
+        let procedure = procedure(&["move 1 from 2 to 1", "move 4 from 1 to 3"]);
+        let err = stacks.execute(&procedure, &CrateMover9001).unwrap_err();
+        assert_eq!(err.instruction_number, 2);
+        assert_eq!(err.instruction.to_string(), "move 4 from 1 to 3");
+        assert_eq!(
+            err.reason,
+            InvalidInstruction::NotEnoughCrates {
+                available: 3,
+                requested: 4
+            }
+        );
+        assert_eq!(err.stacks.top(), "DCP");
+    }
+
+    #[test]
+    fn dry_run_leaves_stacks_untouched() {
+        let stacks = example_stacks();
+        let procedure = procedure(&["move 1 from 2 to 1", "move 1 from 1 to 4"]);
+        let err = stacks.dry_run(&procedure, &CrateMover9000).unwrap_err();
+        assert_eq!(err.reason, InvalidInstruction::NoSuchStack(3));
+        assert_eq!(stacks.top(), "NDP");
+    }
+
+    fn arrange(name: &str, lifted: &str) -> String {
+        let mover = crate_mover_by_name(name).unwrap();
+        mover
+            .arrange(lifted.chars().collect())
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn crane_models() {
+        assert_eq!(arrange("9000", "abcde"), "edcba");
+        assert_eq!(arrange("9001", "abcde"), "abcde");
+        assert_eq!(arrange("limited:2", "abcde"), "debca");
+        assert_eq!(arrange("limited:5", "abcde"), "abcde");
+        assert_eq!(arrange("limited:1", "abcde"), "edcba");
+        assert_eq!(arrange("alternating", "abcde"), "ebcda");
+        assert_eq!(arrange("alternating", "abcdef"), "ebcdaf");
+        assert!(crate_mover_by_name("limited:0").is_err());
+        assert!(crate_mover_by_name("9002").is_err());
+    }
+
+    #[test]
+    fn history_highlights_moved_crates() {
+        let procedure = procedure(&["move 2 from 2 to 3", "move 1 from 1 to 2"]);
+        let history = example_stacks().record(procedure, &CrateMover9000).unwrap();
+        assert_eq!(history.last_step(), 2);
+        assert_eq!(history.snapshots[1].top(), "NMC");
+        let frame = history.frame(1).to_string();
+        assert!(frame.starts_with("Step 1/2: move 2 from 2 to 3\n"));
+        for moved in ["[D]", "[C]"] {
+            assert!(frame.contains(&format!("{}{}{}", HIGHLIGHT, moved, RESET)));
+        }
+        assert!(!history.frame(0).to_string().contains(HIGHLIGHT));
+    }
+
+    #[test]
+    fn input_round_trip() -> aoc22::MyResult<()> {
+        let input = include_str!("../inputs/input5.txt");
+        let puzzle: Puzzle = input.parse()?;
+        assert_eq!(puzzle.stacks.stacks.len(), 9);
+        assert_eq!(puzzle.to_string(), input);
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip_with_many_stacks() -> aoc22::MyResult<()> {
+        let mut stacks = vec![Stack::new(); 12];
+        stacks[0] = vec!['A', 'B'];
+        stacks[9] = vec!['Z'];
+        let puzzle = Puzzle {
+            stacks: Stacks { stacks },
+            procedure: procedure(&["move 1 from 10 to 12", "move 2 from 1 to 11"]),
+        };
+        let drawing = [
+            format!("[B]{}", " ".repeat(44)),
+            format!("[A]{}[Z]{}", " ".repeat(33), " ".repeat(8)),
+            String::from(" 1   2   3   4   5   6   7   8   9  10  11  12 "),
+            String::new(),
+            String::from("move 1 from 10 to 12"),
+            String::from("move 2 from 1 to 11\n"),
+        ]
+        .join("\n");
+        assert_eq!(puzzle.to_string(), drawing);
+        assert_eq!(drawing.parse::<Puzzle>()?, puzzle);
+        Ok(())
+    }
+
+    #[test]
+    fn bad_drawings_are_rejected() {
+        for bad in [
+            "[A] [B]\n 1 \n\n",
+            "[A]    \n    [B]\n 1   2 \n\n",
+            "[A] \n 2 \n\n",
+            "(A)\n 1 \n\n",
+        ] {
+            assert!(bad.parse::<Puzzle>().is_err(), "{:?}", bad);
+        }
+    }
+
+    fn puzzles() -> impl Strategy<Value = Puzzle> {
+        let stack = prop::collection::vec(prop::char::range('A', 'Z'), 0..6);
+        (1usize..15)
+            .prop_flat_map(move |n| {
+                let instruction =
+                    (1usize..10, 0..n, 0..n).prop_map(|(count, source, dest)| Instruction {
+                        count,
+                        source,
+                        dest,
+                    });
+                (
+                    prop::collection::vec(stack.clone(), n),
+                    prop::collection::vec(instruction, 0..5),
+                )
+            })
+            .prop_map(|(stacks, procedure)| Puzzle {
+                stacks: Stacks { stacks },
+                procedure,
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn serialised_puzzles_parse_back(puzzle in puzzles()) {
+            let serialised = puzzle.to_string();
+            let parsed: Puzzle = serialised.parse().unwrap();
+            prop_assert_eq!(&parsed, &puzzle);
+            prop_assert_eq!(parsed.to_string(), serialised);
+        }
+    }
 }
@@ -1,23 +1,327 @@
-use std::collections::HashSet;
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{BufReader, Read},
+};
 
-fn main() {
-    day6_1();
-    day6_2();
+fn main() -> aoc22::MyResult<()> {
+    // every marker of a captured datastream can be listed with
+    // `cargo run --bin day6 -- <file> <marker size>`,
+    // and its frames with `cargo run --bin day6 -- --decode <file> <marker size>`
+    match &std::env::args().skip(1).collect::<Vec<_>>()[..] {
+        [path, marker_size] => {
+            for position in markers_in_reader(File::open(path)?, marker_size.parse()?)? {
+                println!("{}", position?);
+            }
+            return Ok(());
+        }
+        [flag, path, marker_size] if flag == "--decode" => {
+            let datastream = std::fs::read(path)?;
+            for frame in Decoder::new(&datastream, marker_size.parse()?) {
+                let frame = frame?;
+                println!(
+                    "{}: {}",
+                    frame.offset,
+                    String::from_utf8_lossy(frame.payload)
+                );
+            }
+            return Ok(());
+        }
+        _ => (),
+    }
+    let (part1, part2) = day6(include_str!("../inputs/input6.txt"))?;
+    println!("part1: {}", part1);
+    println!("part2: {}", part2);
+    Ok(())
 }
 
-fn find_marker_position(marker_size: usize) -> Option<usize> {
-    let s: Vec<char> = include_str!("../inputs/input6.txt").chars().collect();
-    s.windows(marker_size)
-        .enumerate()
-        .map(|(n, marker)| (n, marker.iter().copied().collect::<HashSet<char>>()))
-        .filter(|(_, marker_set)| marker_set.len() == marker_size)
-        .map(|(n, _)| n + marker_size)
+const START_OF_PACKET_SIZE: usize = 4;
+const START_OF_MESSAGE_SIZE: usize = 14;
+
+pub fn day6(input: &str) -> aoc22::MyResult<(usize, usize)> {
+    let part1 = find_marker_position(input.as_bytes(), START_OF_PACKET_SIZE)
+        .ok_or("No start-of-packet marker")?;
+    let part2 = find_marker_position(input.as_bytes(), START_OF_MESSAGE_SIZE)
+        .ok_or("No start-of-message marker")?;
+    Ok((part1, part2))
+}
+
+/// Position right after the first marker of the given size, None if there is none
+/// or if the size is 0
+fn find_marker_position(datastream: &[u8], marker_size: usize) -> Option<usize> {
+    markers(datastream.iter().copied(), marker_size)
+        .ok()?
         .next()
 }
 
-fn day6_1() {
-    println!("{}", find_marker_position(4).unwrap());
+/// A payload of the communication system, enclosed between two markers
+#[derive(Debug, PartialEq)]
+struct Frame<'a> {
+    /// position of the first byte of the payload in the datastream
+    offset: usize,
+    payload: &'a [u8],
+}
+
+#[derive(Debug, PartialEq)]
+enum DecodeError {
+    /// Markers must be at least one byte long
+    InvalidMarkerSize,
+    /// The datastream doesn't contain any marker
+    NoMarker,
+    /// The frame starting at this position isn't closed by a marker
+    TruncatedFrame { offset: usize },
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidMarkerSize => write!(f, "{}", ZeroMarkerSize),
+            Self::NoMarker => write!(f, "no marker in the datastream"),
+            Self::TruncatedFrame { offset } => {
+                write!(
+                    f,
+                    "the frame at position {} is not closed by a marker",
+                    offset
+                )
+            }
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Splits a datastream into the frames found between consecutive markers.
+/// Anything before the first marker is ignored, and markers never overlap.
+struct Decoder<'a> {
+    datastream: &'a [u8],
+    marker_size: usize,
+    /// end of the last marker found, None until the first marker is found
+    position: Option<usize>,
+    done: bool,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(datastream: &'a [u8], marker_size: usize) -> Decoder<'a> {
+        Decoder {
+            datastream,
+            marker_size,
+            position: None,
+            done: false,
+        }
+    }
+
+    fn fail(&mut self, error: DecodeError) -> Option<Result<Frame<'a>, DecodeError>> {
+        self.done = true;
+        Some(Err(error))
+    }
+}
+
+impl<'a> Iterator for Decoder<'a> {
+    type Item = Result<Frame<'a>, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if self.marker_size == 0 {
+            return self.fail(DecodeError::InvalidMarkerSize);
+        }
+        let offset = match self.position {
+            Some(offset) => offset,
+            None => match find_marker_position(self.datastream, self.marker_size) {
+                Some(first_marker_end) => first_marker_end,
+                None => return self.fail(DecodeError::NoMarker),
+            },
+        };
+        if offset == self.datastream.len() {
+            self.done = true;
+            return None;
+        }
+        match find_marker_position(&self.datastream[offset..], self.marker_size) {
+            Some(marker_end) => {
+                let payload_end = offset + marker_end - self.marker_size;
+                self.position = Some(offset + marker_end);
+                Some(Ok(Frame {
+                    offset,
+                    payload: &self.datastream[offset..payload_end],
+                }))
+            }
+            None => self.fail(DecodeError::TruncatedFrame { offset }),
+        }
+    }
+}
+
+/// Markers can't be empty
+#[derive(Debug, PartialEq)]
+struct ZeroMarkerSize;
+
+impl Display for ZeroMarkerSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "markers must be at least one character long")
+    }
+}
+
+impl Error for ZeroMarkerSize {}
+
+/// The last `size` bytes of a stream, with a count of each byte value to detect duplicates in O(1)
+struct RollingWindow {
+    size: usize,
+    window: VecDeque<u8>,
+    counts: [usize; 256],
+    /// number of byte values appearing more than once in the window
+    duplicates: usize,
+}
+
+impl RollingWindow {
+    fn new(size: usize) -> Result<RollingWindow, ZeroMarkerSize> {
+        if size == 0 {
+            return Err(ZeroMarkerSize);
+        }
+        Ok(RollingWindow {
+            size,
+            window: VecDeque::with_capacity(size),
+            counts: [0; 256],
+            duplicates: 0,
+        })
+    }
+
+    /// Add a byte to the window, and return whether the window is now a marker
+    fn push(&mut self, byte: u8) -> bool {
+        if self.window.len() == self.size {
+            let old = self.window.pop_front().unwrap();
+            self.counts[old as usize] -= 1;
+            if self.counts[old as usize] == 1 {
+                self.duplicates -= 1;
+            }
+        }
+        self.counts[byte as usize] += 1;
+        if self.counts[byte as usize] == 2 {
+            self.duplicates += 1;
+        }
+        self.window.push_back(byte);
+        self.window.len() == self.size && self.duplicates == 0
+    }
+}
+
+/// Positions right after every marker of the given size in the datastream
+fn markers(
+    datastream: impl IntoIterator<Item = u8>,
+    marker_size: usize,
+) -> Result<impl Iterator<Item = usize>, ZeroMarkerSize> {
+    let mut window = RollingWindow::new(marker_size)?;
+    Ok(datastream
+        .into_iter()
+        .enumerate()
+        .filter_map(move |(n, byte)| window.push(byte).then_some(n + 1)))
+}
+
+/// Positions right after every marker of the given size in a datastream read from `reader`
+fn markers_in_reader(
+    reader: impl Read,
+    marker_size: usize,
+) -> Result<impl Iterator<Item = std::io::Result<usize>>, ZeroMarkerSize> {
+    let mut window = RollingWindow::new(marker_size)?;
+    Ok(BufReader::new(reader)
+        .bytes()
+        .enumerate()
+        .filter_map(move |(n, byte)| match byte {
+            Ok(byte) => window.push(byte).then_some(Ok(n + 1)),
+            Err(e) => Some(Err(e)),
+        }))
 }
-fn day6_2() {
-    println!("{}", find_marker_position(14).unwrap());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const EXAMPLES: [(&str, usize, usize); 4] = [
+        ("bvwbjplbgvbhsrlpgdmjqwftvncz", 5, 23),
+        ("nppdvjthqldpwncqszvftbrmjlhg", 6, 23),
+        ("nznrnfrfntjfmvfwmzdfjlvtqnbhcprsg", 10, 29),
+        ("zcfzfwzzqfrljwzlrfnpqdbhtmscgvjw", 11, 26),
+    ];
+
+    fn decode(datastream: &str, marker_size: usize) -> Result<Vec<(usize, &str)>, DecodeError> {
+        Decoder::new(datastream.as_bytes(), marker_size)
+            .map(|frame| frame.map(|f| (f.offset, std::str::from_utf8(f.payload).unwrap())))
+            .collect()
+    }
+
+    #[test]
+    fn decoder() {
+        // junk, marker, payload, marker, empty payload, marker
+        assert_eq!(
+            decode("aaabcdddddefgdefg", 4),
+            Ok(vec![(6, "ddd"), (13, "")])
+        );
+        assert_eq!(decode("abcd", 4), Ok(vec![]));
+        assert_eq!(
+            decode("abcdxxxyyy", 4),
+            Err(DecodeError::TruncatedFrame { offset: 4 })
+        );
+        assert_eq!(decode("aabbaabb", 4), Err(DecodeError::NoMarker));
+        assert_eq!(decode("abcd", 0), Err(DecodeError::InvalidMarkerSize));
+        assert_eq!(
+            Decoder::new(b"abcd", 0).collect::<Vec<_>>(),
+            vec![Err(DecodeError::InvalidMarkerSize)]
+        );
+        assert_eq!(
+            decode("aabbaabb", 2),
+            Err(DecodeError::TruncatedFrame { offset: 7 })
+        );
+    }
+
+    #[test]
+    fn packets_and_messages() {
+        let datastream = b"nppdvjthqldpwncqszvftbrmjlhg";
+        let first_packet = Decoder::new(datastream, START_OF_PACKET_SIZE)
+            .next()
+            .unwrap();
+        assert_eq!(first_packet.map(|f| f.offset), Ok(6));
+        let messages = Decoder::new(datastream, START_OF_MESSAGE_SIZE).collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![Err(DecodeError::TruncatedFrame { offset: 23 })]
+        );
+    }
+
+    #[test]
+    fn examples() -> aoc22::MyResult<()> {
+        for (datastream, part1, part2) in EXAMPLES {
+            assert_eq!(day6(datastream)?, (part1, part2), "{}", datastream);
+        }
+        Ok(())
+    }
+
+    /// every window of distinct characters, computed with a set per window
+    fn naive_markers(datastream: &str, marker_size: usize) -> Vec<usize> {
+        let s: Vec<char> = datastream.chars().collect();
+        s.windows(marker_size)
+            .enumerate()
+            .filter(|(_, marker)| marker.iter().collect::<HashSet<_>>().len() == marker_size)
+            .map(|(n, _)| n + marker_size)
+            .collect()
+    }
+
+    #[test]
+    fn matches_naive_detection() -> aoc22::MyResult<()> {
+        let input = include_str!("../inputs/input6.txt").trim();
+        for marker_size in 1..=20 {
+            let expected = naive_markers(input, marker_size);
+            assert_eq!(
+                markers(input.bytes(), marker_size)?.collect::<Vec<_>>(),
+                expected
+            );
+            let from_reader =
+                markers_in_reader(input.as_bytes(), marker_size)?.collect::<Result<Vec<_>, _>>()?;
+            assert_eq!(from_reader, expected);
+        }
+        assert!(markers(input.bytes(), 0).is_err());
+        assert!(markers_in_reader(input.as_bytes(), 0).is_err());
+        assert_eq!(find_marker_position(input.as_bytes(), 0), None);
+        Ok(())
+    }
 }
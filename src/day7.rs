@@ -1,105 +1,654 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io,
+    path::{Component, Path, PathBuf},
+};
 
-use itertools::Itertools;
-
-aoc22::main!(day7, "../inputs/input7.txt");
+fn main() -> aoc22::MyResult<()> {
+    // the filesystem of a transcript can be queried with
+    // `cargo run --bin day7 -- <transcript> <du|find|tree|largest|plan|export> [args]`
+    // and a transcript of a real directory can be created with `cargo run --bin day7 -- --import <dir>`
+    match &std::env::args().skip(1).collect::<Vec<_>>()[..] {
+        [flag, dir] if flag == "--import" => {
+            print!("{}", import(Path::new(dir))?);
+            return Ok(());
+        }
+        [path, command, args @ ..] => {
+            let fs = FileSystem::from_transcript(&std::fs::read_to_string(path)?)?;
+            print!("{}", run_command(&fs, command, args)?);
+            return Ok(());
+        }
+        _ => (),
+    }
+    let (part1, part2) = day7(include_str!("../inputs/input7.txt"))?;
+    println!("part1: {}", part1);
+    println!("part2: {}", part2);
+    Ok(())
+}
 
 aoc22::test_with_example!(day7, "../inputs/example7.txt", 95437, 24933642);
 
-#[derive(Debug, Clone, Default)]
-struct DirPos {
-    full_name: String,
-    line: usize,
-}
-
-const CD_CMD: &str = "$ cd ";
 const TOTAL_SPACE: usize = 70000000;
 const REQUIRED_SPACE: usize = 30000000;
 
 fn day7(input: &str) -> aoc22::MyResult<(usize, usize)> {
-    let lines = input.lines().collect_vec();
-    let dir_index = create_dir_index(&lines);
-    let dir_sizes = compute_sizes(&lines, dir_index);
+    let fs = FileSystem::from_transcript(input)?;
+    let dir_sizes = compute_sizes(&fs);
 
     let part1 = dir_sizes
         .values()
         .filter(|&&size| size <= 100000)
         .sum::<usize>();
 
-    let free_space = TOTAL_SPACE - dir_sizes["/"];
-    let to_free = REQUIRED_SPACE - free_space;
-    let &part2 = dir_sizes
-        .values()
-        .filter(|&&size| size >= to_free)
-        .min()
-        .unwrap();
+    let plan = plan_deletion(&fs, &Disk::default())?;
+    let part2 = plan.freed;
 
     Ok((part1, part2))
 }
 
-/// Create an index storing the starting line of each directories
-fn create_dir_index(lines: &[&str]) -> Vec<DirPos> {
-    let cd_lines = lines
-        .iter()
-        .enumerate()
-        .filter(|(_, line)| line.starts_with(CD_CMD));
-    let mut dir_index = Vec::new();
-    let mut add_dir_to_index = |full_name: &String, line| {
-        dir_index.push(DirPos {
-            full_name: full_name.clone(),
-            line,
-        })
+fn run_command(fs: &FileSystem, command: &str, args: &[String]) -> aoc22::MyResult<String> {
+    let arg = |i: usize, default: usize| -> aoc22::MyResult<usize> {
+        match args.get(i) {
+            Some(arg) => Ok(arg.trim_start_matches('+').parse()?),
+            None => Ok(default),
+        }
     };
-    let mut cur_dir = String::new();
-    for (i, cd_line) in cd_lines {
-        match &cd_line[CD_CMD.len()..] {
-            ".." => {
-                cur_dir = match cur_dir.rsplit_once('/') {
-                    Some((prefix, _)) => String::from(prefix),
-                    None => String::new(),
+    let lines = match command {
+        "du" => du(fs),
+        "find" => find_larger(fs, arg(0, 0)?),
+        "tree" => tree(fs),
+        "largest" => largest_files(fs, arg(0, 10)?)
+            .into_iter()
+            .map(|(path, size)| format!("{}\t{}", size, path))
+            .collect(),
+        "plan" => {
+            let disk = Disk {
+                total_space: arg(0, TOTAL_SPACE)?,
+                required_space: arg(1, REQUIRED_SPACE)?,
+            };
+            let plan = plan_deletion(fs, &disk)?;
+            let mut lines = plan.dirs;
+            lines.push(format!("freed: {} (/ itself is never deleted)", plan.freed));
+            lines
+        }
+        "export" => {
+            let dir = args.first().ok_or("Missing export directory")?;
+            export(fs, Path::new(dir))?;
+            Vec::new()
+        }
+        _ => return Err(format!("Unknown command {}", command).into()),
+    };
+    Ok(lines.into_iter().map(|l| l + "\n").collect())
+}
+
+type NodeId = usize;
+
+const ROOT: NodeId = 0;
+
+#[derive(Debug, PartialEq)]
+enum Node {
+    Dir { children: BTreeMap<String, NodeId> },
+    File { size: usize },
+}
+
+#[derive(Debug)]
+struct Entry {
+    name: String,
+    parent: Option<NodeId>,
+    node: Node,
+}
+
+/// Directory tree of the device, stored as a list of entries where the root is the first one
+#[derive(Debug)]
+struct FileSystem {
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, PartialEq)]
+enum TranscriptError {
+    /// A line that is neither a known command nor part of the output of `ls` (1-based line number)
+    Unexpected { line: usize },
+    /// `cd` into something that was listed as a file
+    NotADirectory { line: usize, path: String },
+    /// A `ls` output that contradicts what is already known about the directory
+    InconsistentListing { line: usize, path: String },
+    /// An entry name that isn't a single component of a path, like `..` or `a/b`
+    BadName { line: usize, name: String },
+}
+
+impl Display for TranscriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unexpected { line } => write!(f, "line {}: unexpected line", line),
+            Self::NotADirectory { line, path } => {
+                write!(f, "line {}: {} is not a directory", line, path)
+            }
+            Self::InconsistentListing { line, path } => {
+                write!(f, "line {}: inconsistent listing of {}", line, path)
+            }
+            Self::BadName { line, name } => write!(f, "line {}: bad name {:?}", line, name),
+        }
+    }
+}
+
+impl Error for TranscriptError {}
+
+impl FileSystem {
+    fn new() -> FileSystem {
+        FileSystem {
+            entries: vec![Entry {
+                name: String::new(),
+                parent: None,
+                node: Node::Dir {
+                    children: BTreeMap::new(),
+                },
+            }],
+        }
+    }
+
+    /// Rebuild the directory tree by replaying the `cd` and `ls` commands of a terminal transcript
+    fn from_transcript(transcript: &str) -> Result<FileSystem, TranscriptError> {
+        let mut fs = FileSystem::new();
+        let mut cwd = ROOT;
+        // line of the `ls` whose output is being read, and the names it listed so far
+        let mut listing: Option<(usize, HashSet<&str>)> = None;
+        for (i, line) in transcript.lines().enumerate() {
+            let line_number = i + 1;
+            let check_name = |name: &str| {
+                if valid_name(name) {
+                    Ok(())
+                } else {
+                    Err(TranscriptError::BadName {
+                        line: line_number,
+                        name: name.to_owned(),
+                    })
+                }
+            };
+            if line.starts_with('$') {
+                if let Some((ls_line, names)) = listing.take() {
+                    fs.check_listing(cwd, ls_line, &names)?;
                 }
             }
-            "/" => {
-                cur_dir = String::from("/");
-                add_dir_to_index(&cur_dir, i);
+            if line.is_empty() {
+                continue;
+            } else if line == "$ ls" {
+                listing = Some((line_number, HashSet::new()));
+            } else if let Some(target) = line.strip_prefix("$ cd ") {
+                if target != "/" && target != ".." {
+                    check_name(target)?;
+                }
+                cwd = match target {
+                    "/" => ROOT,
+                    ".." => fs.entries[cwd].parent.unwrap_or(ROOT),
+                    name => fs
+                        .enter(cwd, name)
+                        .ok_or_else(|| TranscriptError::NotADirectory {
+                            line: line_number,
+                            path: join_dir(&fs.path(cwd), name),
+                        })?,
+                };
+            } else if let (Some((ls_line, names)), Some((kind, name))) =
+                (&mut listing, line.split_once(' '))
+            {
+                check_name(name)?;
+                let node = match kind {
+                    "dir" => Node::Dir {
+                        children: BTreeMap::new(),
+                    },
+                    size => Node::File {
+                        size: size
+                            .parse()
+                            .map_err(|_| TranscriptError::Unexpected { line: line_number })?,
+                    },
+                };
+                if !fs.record(cwd, name, node) || !names.insert(name) {
+                    return Err(TranscriptError::InconsistentListing {
+                        line: *ls_line,
+                        path: fs.path(cwd),
+                    });
+                }
+            } else {
+                return Err(TranscriptError::Unexpected { line: line_number });
             }
-            x => {
-                cur_dir = join_dir(&cur_dir, x);
-                add_dir_to_index(&cur_dir, i);
+        }
+        if let Some((ls_line, names)) = listing {
+            fs.check_listing(cwd, ls_line, &names)?;
+        }
+        Ok(fs)
+    }
+
+    fn children(&self, dir: NodeId) -> Option<&BTreeMap<String, NodeId>> {
+        match &self.entries[dir].node {
+            Node::Dir { children } => Some(children),
+            Node::File { .. } => None,
+        }
+    }
+
+    fn add(&mut self, parent: NodeId, name: &str, node: Node) -> NodeId {
+        let id = self.entries.len();
+        self.entries.push(Entry {
+            name: name.to_owned(),
+            parent: Some(parent),
+            node,
+        });
+        if let Node::Dir { children } = &mut self.entries[parent].node {
+            children.insert(name.to_owned(), id);
+        }
+        id
+    }
+
+    /// Get the sub directory `name` of `dir`, creating it if it isn't known yet.
+    /// Returns None if `name` is a file.
+    fn enter(&mut self, dir: NodeId, name: &str) -> Option<NodeId> {
+        match self.children(dir)?.get(name) {
+            Some(&child) => self.children(child).map(|_| child),
+            None => Some(self.add(
+                dir,
+                name,
+                Node::Dir {
+                    children: BTreeMap::new(),
+                },
+            )),
+        }
+    }
+
+    /// Record an entry listed in `dir`. Returns false if it contradicts a previous entry with the same name.
+    fn record(&mut self, dir: NodeId, name: &str, node: Node) -> bool {
+        match self.children(dir).and_then(|children| children.get(name)) {
+            Some(&existing) => match (&self.entries[existing].node, &node) {
+                (Node::Dir { .. }, Node::Dir { .. }) => true,
+                (existing, node) => existing == node,
+            },
+            None => {
+                self.add(dir, name, node);
+                true
             }
         }
     }
-    dir_index
+
+    /// Check that a complete listing of `dir` contains every entry already known in it
+    fn check_listing(
+        &self,
+        dir: NodeId,
+        ls_line: usize,
+        names: &HashSet<&str>,
+    ) -> Result<(), TranscriptError> {
+        let complete = self
+            .children(dir)
+            .into_iter()
+            .flat_map(BTreeMap::keys)
+            .all(|name| names.contains(name.as_str()));
+        if complete {
+            Ok(())
+        } else {
+            Err(TranscriptError::InconsistentListing {
+                line: ls_line,
+                path: self.path(dir),
+            })
+        }
+    }
+
+    /// Every entry of the tree below `id` (included), directories before their content
+    fn walk(&self, id: NodeId) -> Vec<NodeId> {
+        let mut entries = vec![id];
+        for &child in self.children(id).into_iter().flat_map(BTreeMap::values) {
+            entries.extend(self.walk(child));
+        }
+        entries
+    }
+
+    fn is_dir(&self, id: NodeId) -> bool {
+        matches!(self.entries[id].node, Node::Dir { .. })
+    }
+
+    fn path(&self, id: NodeId) -> String {
+        match self.entries[id].parent {
+            Some(parent) => join_dir(&self.path(parent), &self.entries[id].name),
+            None => String::from("/"),
+        }
+    }
+}
+
+/// Compute the size of every entry, indexed by NodeId
+fn entry_sizes(fs: &FileSystem) -> Vec<usize> {
+    let mut sizes = vec![0; fs.entries.len()];
+    // entries are always added after their parent,
+    // so going in reverse adds the size of each entry to its parent once it is complete
+    for (id, entry) in fs.entries.iter().enumerate().rev() {
+        if let Node::File { size } = entry.node {
+            sizes[id] = size;
+        }
+        if let Some(parent) = entry.parent {
+            sizes[parent] += sizes[id];
+        }
+    }
+    sizes
 }
 
 /// Compute the size of each directory and return it in a HashMap
-fn compute_sizes(lines: &[&str], dir_index: Vec<DirPos>) -> HashMap<String, usize> {
-    let mut dir_sizes: HashMap<String, usize> = HashMap::new();
-    dir_index
+fn compute_sizes(fs: &FileSystem) -> HashMap<String, usize> {
+    let sizes = entry_sizes(fs);
+    (0..fs.entries.len())
+        .filter(|&id| fs.is_dir(id))
+        .map(|id| (fs.path(id), sizes[id]))
+        .collect()
+}
+
+/// Size in the style of `du -h`, rounded up
+fn human_size(size: usize) -> String {
+    let mut value = size as f64;
+    let mut units = ["K", "M", "G", "T"].iter();
+    let mut unit = "";
+    while value >= 1024.0 {
+        match units.next() {
+            Some(next) => unit = next,
+            None => break,
+        }
+        value /= 1024.0;
+    }
+    if unit.is_empty() {
+        size.to_string()
+    } else if value < 10.0 {
+        format!("{:.1}{}", (value * 10.0).ceil() / 10.0, unit)
+    } else {
+        format!("{:.0}{}", value.ceil(), unit)
+    }
+}
+
+/// Size of every directory, sub directories first, like `du -h`
+fn du(fs: &FileSystem) -> Vec<String> {
+    fn visit(fs: &FileSystem, sizes: &[usize], id: NodeId, lines: &mut Vec<String>) {
+        for &child in fs.children(id).into_iter().flat_map(BTreeMap::values) {
+            if fs.is_dir(child) {
+                visit(fs, sizes, child, lines);
+            }
+        }
+        lines.push(format!("{}\t{}", human_size(sizes[id]), fs.path(id)));
+    }
+    let sizes = entry_sizes(fs);
+    let mut lines = Vec::new();
+    visit(fs, &sizes, ROOT, &mut lines);
+    lines
+}
+
+/// Every file bigger than `min_size`, like `find -size +N`
+fn find_larger(fs: &FileSystem, min_size: usize) -> Vec<String> {
+    fs.walk(ROOT)
         .into_iter()
-        // compute sizes in reserve to be sure that the size of an inner dir is computed before the outer dir
-        .rev()
-        .for_each(|current_dir| {
-            // the line just after cd should be a 'ls'
-            assert_eq!(lines[current_dir.line + 1], "$ ls");
-            // for the current dir, just read the output of its direct 'ls' command
-            // the size of all its subdirs are already computed
-            let ls_output = lines[current_dir.line + 2..]
-                .iter()
-                .take_while(|l| !l.starts_with('$'));
-            let current_size: usize = ls_output
-                .filter_map(|l| match l.split_once(' ') {
-                    Some(("dir", d)) => {
-                        let sub_dir = join_dir(&current_dir.full_name, d);
-                        Some(dir_sizes[&sub_dir])
+        .filter(|&id| matches!(fs.entries[id].node, Node::File { size } if size > min_size))
+        .map(|id| fs.path(id))
+        .collect()
+}
+
+/// The whole tree with the size of each entry, like `tree --du`
+fn tree(fs: &FileSystem) -> Vec<String> {
+    fn draw(fs: &FileSystem, sizes: &[usize], id: NodeId, prefix: &str, lines: &mut Vec<String>) {
+        let children = fs.children(id).into_iter().flat_map(BTreeMap::values);
+        let count = children.clone().count();
+        for (i, &child) in children.enumerate() {
+            let (branch, indent) = if i + 1 == count {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            let name = &fs.entries[child].name;
+            lines.push(format!("{}{}{} ({})", prefix, branch, name, sizes[child]));
+            draw(fs, sizes, child, &(prefix.to_owned() + indent), lines);
+        }
+    }
+    let sizes = entry_sizes(fs);
+    let mut lines = vec![format!("/ ({})", sizes[ROOT])];
+    draw(fs, &sizes, ROOT, "", &mut lines);
+    lines
+}
+
+/// The `n` biggest files with their size, biggest first
+fn largest_files(fs: &FileSystem, n: usize) -> Vec<(String, usize)> {
+    let mut files: Vec<_> = fs
+        .entries
+        .iter()
+        .enumerate()
+        .filter_map(|(id, entry)| match entry.node {
+            Node::File { size } => Some((fs.path(id), size)),
+            Node::Dir { .. } => None,
+        })
+        .collect();
+    files.sort_by(|(path_a, size_a), (path_b, size_b)| {
+        size_b.cmp(size_a).then_with(|| path_a.cmp(path_b))
+    });
+    files.truncate(n);
+    files
+}
+
+/// Where an entry is exported in `root`, None if one of the names on its path isn't a plain
+/// file name that stays inside `root`
+fn export_path(fs: &FileSystem, root: &Path, mut id: NodeId) -> Option<PathBuf> {
+    let mut names = Vec::new();
+    while let Some(parent) = fs.entries[id].parent {
+        let name = fs.entries[id].name.as_str();
+        match Path::new(name).components().collect::<Vec<_>>()[..] {
+            [Component::Normal(component)] if component == name => names.push(name),
+            _ => return None,
+        }
+        id = parent;
+    }
+    Some(
+        names
+            .into_iter()
+            .rev()
+            .fold(root.to_path_buf(), |path, name| path.join(name)),
+    )
+}
+
+/// Write the filesystem in the directory `root`, as sparse files of the recorded sizes.
+/// Nothing is written if an entry would end up outside of `root`.
+fn export(fs: &FileSystem, root: &Path) -> io::Result<()> {
+    let paths = fs
+        .walk(ROOT)
+        .into_iter()
+        .map(|id| {
+            let path = export_path(fs, root, id).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Can't export {:?} inside {}", fs.path(id), root.display()),
+                )
+            })?;
+            Ok((id, path))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    for (id, path) in paths {
+        match fs.entries[id].node {
+            Node::Dir { .. } => std::fs::create_dir_all(&path)?,
+            Node::File { size } => File::create(&path)?.set_len(size as u64)?,
+        }
+    }
+    Ok(())
+}
+
+/// Create a transcript of the `cd` and `ls` commands exploring the real directory `root`.
+/// Anything that is neither a file nor a directory, like symlinks, is ignored.
+fn import(root: &Path) -> io::Result<String> {
+    fn explore(dir: &Path, transcript: &mut String) -> io::Result<()> {
+        let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        transcript.push_str("$ ls\n");
+        let mut sub_dirs = Vec::new();
+        for entry in entries {
+            let name = entry.file_name().into_string().map_err(|name| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("File name is not UTF-8: {:?}", name),
+                )
+            })?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                transcript.push_str(&format!("dir {}\n", name));
+                sub_dirs.push(name);
+            } else if metadata.is_file() {
+                transcript.push_str(&format!("{} {}\n", metadata.len(), name));
+            }
+        }
+        for name in sub_dirs {
+            transcript.push_str(&format!("$ cd {}\n", name));
+            explore(&dir.join(name), transcript)?;
+            transcript.push_str("$ cd ..\n");
+        }
+        Ok(())
+    }
+    let mut transcript = String::from("$ cd /\n");
+    explore(root, &mut transcript)?;
+    Ok(transcript)
+}
+
+#[derive(Debug)]
+struct Disk {
+    total_space: usize,
+    required_space: usize,
+}
+
+impl Default for Disk {
+    fn default() -> Self {
+        Disk {
+            total_space: TOTAL_SPACE,
+            required_space: REQUIRED_SPACE,
+        }
+    }
+}
+
+/// Directories to delete to get the required free space
+#[derive(Debug, PartialEq)]
+struct DeletionPlan {
+    dirs: Vec<String>,
+    freed: usize,
+}
+
+/// Find the fewest directories to delete to get enough free space on the disk,
+/// and among those, the ones that free the least space. The root directory is never deleted.
+fn plan_deletion(fs: &FileSystem, disk: &Disk) -> aoc22::MyResult<DeletionPlan> {
+    let sizes = entry_sizes(fs);
+    let free_space = disk
+        .total_space
+        .checked_sub(sizes[ROOT])
+        .ok_or("The files don't fit on the disk")?;
+    let to_free = disk.required_space.saturating_sub(free_space);
+
+    let most = most_freed_within(fs, &sizes, ROOT);
+    let count = most
+        .iter()
+        .position(|&freed| freed >= to_free)
+        .ok_or("Not enough space can be freed")?;
+    let search = PlanSearch {
+        fs,
+        sizes: &sizes,
+        to_free,
+        count,
+        most: &most,
+    };
+    let (freed, mut dirs) = search
+        .plans_within(ROOT)
+        .swap_remove(count)
+        .into_iter()
+        .find(|&(freed, _)| freed >= to_free)
+        .ok_or("Not enough space can be freed")?;
+    dirs.sort_by_key(|&id| (std::cmp::Reverse(sizes[id]), fs.path(id)));
+    Ok(DeletionPlan {
+        dirs: dirs.into_iter().map(|id| fs.path(id)).collect(),
+        freed,
+    })
+}
+
+/// Most space freed by deleting k directories strictly inside `dir`, for every possible k
+fn most_freed_within(fs: &FileSystem, sizes: &[usize], dir: NodeId) -> Vec<usize> {
+    let mut most = vec![0];
+    for &child in fs.children(dir).into_iter().flat_map(BTreeMap::values) {
+        if !fs.is_dir(child) {
+            continue;
+        }
+        let mut in_child = most_freed_within(fs, sizes, child);
+        // deleting the child itself frees more than deleting any of its sub directories
+        match in_child.get_mut(1) {
+            Some(one) => *one = sizes[child],
+            None => in_child.push(sizes[child]),
+        }
+        let mut merged = vec![0; most.len() + in_child.len() - 1];
+        for (a, &freed_a) in most.iter().enumerate() {
+            for (b, &freed_b) in in_child.iter().enumerate() {
+                merged[a + b] = merged[a + b].max(freed_a + freed_b);
+            }
+        }
+        most = merged;
+    }
+    most
+}
+
+/// Plans found so far, for each number of directories: the space they free, and the directories
+type Plans = Vec<BTreeMap<usize, Vec<NodeId>>>;
+
+/// Every way to free space by deleting at most `count` directories, where `count` is the fewest
+/// directories that can free enough space, skipping partial plans that can't lead to a solution
+struct PlanSearch<'a> {
+    fs: &'a FileSystem,
+    sizes: &'a [usize],
+    to_free: usize,
+    count: usize,
+    /// most space freed by deleting k directories anywhere
+    most: &'a [usize],
+}
+
+impl PlanSearch<'_> {
+    fn plans_within(&self, dir: NodeId) -> Plans {
+        let mut plans = vec![BTreeMap::new(); self.count + 1];
+        plans[0].insert(0, Vec::new());
+        for &child in self.fs.children(dir).into_iter().flat_map(BTreeMap::values) {
+            if !self.fs.is_dir(child) {
+                continue;
+            }
+            let mut in_child = self.plans_within(child);
+            if self.count > 0 {
+                self.insert(&mut in_child, 1, self.sizes[child], vec![child]);
+            }
+            let mut merged = vec![BTreeMap::new(); self.count + 1];
+            for (a, plans_a) in plans.iter().enumerate() {
+                for (b, plans_b) in in_child.iter().enumerate().take(self.count + 1 - a) {
+                    for (freed_a, dirs_a) in plans_a {
+                        for (freed_b, dirs_b) in plans_b {
+                            let dirs = dirs_a.iter().chain(dirs_b).copied().collect();
+                            self.insert(&mut merged, a + b, freed_a + freed_b, dirs);
+                        }
                     }
-                    Some((file_size, _)) => file_size.parse::<usize>().ok(),
-                    _ => None,
-                })
-                .sum();
-            dir_sizes.insert(current_dir.full_name, current_size);
-        });
-    dir_sizes
+                }
+            }
+            plans = merged;
+        }
+        plans
+    }
+
+    /// Add a plan of k directories, unless it can't free enough space with the directories left,
+    /// or another plan of k directories already frees enough space while freeing less
+    fn insert(&self, plans: &mut Plans, k: usize, freed: usize, dirs: Vec<NodeId>) {
+        if freed + self.most[self.count - k] < self.to_free {
+            return;
+        }
+        let plans = &mut plans[k];
+        if freed >= self.to_free {
+            match plans.range(self.to_free..).next() {
+                Some((&enough, _)) if enough <= freed => return,
+                Some((&enough, _)) => {
+                    plans.remove(&enough);
+                }
+                None => (),
+            }
+        }
+        plans.entry(freed).or_insert(dirs);
+    }
+}
+
+/// Whether `name` can be the name of an entry in a directory
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/')
 }
 
 fn join_dir(parent: &str, child: &str) -> String {
@@ -109,3 +658,313 @@ fn join_dir(parent: &str, child: &str) -> String {
         parent.to_owned() + "/" + child
     }
 }
+
+#[cfg(test)]
+mod filesystem_tests {
+    use super::*;
+
+    fn sizes(transcript: &str) -> Result<Vec<(String, usize)>, TranscriptError> {
+        let fs = FileSystem::from_transcript(transcript)?;
+        let mut sizes: Vec<_> = compute_sizes(&fs).into_iter().collect();
+        sizes.sort();
+        Ok(sizes)
+    }
+
+    fn expected(sizes: &[(&str, usize)]) -> Result<Vec<(String, usize)>, TranscriptError> {
+        Ok(sizes.iter().map(|&(p, s)| (p.to_owned(), s)).collect())
+    }
+
+    #[test]
+    fn any_command_order() {
+        // `cd` before `ls`, a child listed before its parent, and a directory listed twice
+        let transcript = "$ cd /\n$ cd a\n$ cd b\n$ ls\n10 x\n$ cd ..\n$ ls\n5 y\ndir b\n\
+                          $ cd /\n$ ls\ndir a\n1 z\n$ cd a\n$ ls\ndir b\n5 y\n";
+        assert_eq!(
+            sizes(transcript),
+            expected(&[("/", 16), ("/a", 15), ("/a/b", 10)])
+        );
+    }
+
+    #[test]
+    fn inconsistent_listings() {
+        let inconsistent = |transcript: &str, line: usize, path: &str| {
+            assert_eq!(
+                FileSystem::from_transcript(transcript).unwrap_err(),
+                TranscriptError::InconsistentListing {
+                    line,
+                    path: path.to_owned()
+                }
+            )
+        };
+        // file size changes between two listings
+        inconsistent("$ ls\n1 a\n$ ls\n2 a\n", 3, "/");
+        // file listed twice in the same listing
+        inconsistent("$ ls\n1 a\n1 a\n", 1, "/");
+        // directory visited but missing from the listing of its parent
+        inconsistent("$ cd a\n$ cd ..\n$ ls\n1 b\n", 3, "/");
+        // file replaced by a directory
+        inconsistent("$ ls\n1 a\n$ ls\ndir a\n", 3, "/");
+    }
+
+    #[test]
+    fn malformed_transcripts() {
+        let error = |transcript| FileSystem::from_transcript(transcript).unwrap_err();
+        assert_eq!(
+            error("$ ls\n1 a\n$ cd a\n"),
+            TranscriptError::NotADirectory {
+                line: 3,
+                path: String::from("/a")
+            }
+        );
+        assert_eq!(
+            error("$ rm -rf /\n"),
+            TranscriptError::Unexpected { line: 1 }
+        );
+        assert_eq!(error("1 a\n"), TranscriptError::Unexpected { line: 1 });
+        assert_eq!(
+            error("$ ls\nbig a\n"),
+            TranscriptError::Unexpected { line: 2 }
+        );
+    }
+
+    #[test]
+    fn bad_names() {
+        let bad_name = |transcript: &str, line: usize, name: &str| {
+            assert_eq!(
+                FileSystem::from_transcript(transcript).unwrap_err(),
+                TranscriptError::BadName {
+                    line,
+                    name: name.to_owned()
+                }
+            )
+        };
+        bad_name("$ ls\n5 ../escaped.txt\n", 2, "../escaped.txt");
+        bad_name("$ ls\ndir ..\n", 2, "..");
+        bad_name("$ ls\n1 a\n1 .\n", 3, ".");
+        bad_name("$ ls\ndir \n", 2, "");
+        bad_name("$ cd a/b\n", 1, "a/b");
+        bad_name("$ cd /a\n", 1, "/a");
+        bad_name("$ cd .\n", 1, ".");
+        bad_name("$ cd \n", 1, "");
+        // names can still contain spaces and dots
+        let fs = FileSystem::from_transcript("$ cd .a\n$ ls\n1 b c..d\n").unwrap();
+        assert_eq!(find_larger(&fs, 0), ["/.a/b c..d"]);
+    }
+
+    fn example() -> FileSystem {
+        FileSystem::from_transcript(include_str!("../inputs/example7.txt")).unwrap()
+    }
+
+    #[test]
+    fn du_and_tree() {
+        let fs = example();
+        assert_eq!(du(&fs), ["584\t/a/e", "93K\t/a", "24M\t/d", "47M\t/"]);
+        assert_eq!(
+            tree(&fs)[..5],
+            [
+                "/ (48381165)",
+                "├── a (94853)",
+                "│   ├── e (584)",
+                "│   │   └── i (584)",
+                "│   ├── f (29116)"
+            ]
+        );
+        assert_eq!(tree(&fs).last().unwrap(), "    └── k (7214296)");
+        assert_eq!(human_size(1024), "1.0K");
+        assert_eq!(human_size(1025), "1.1K");
+    }
+
+    #[test]
+    fn find_and_largest() {
+        let fs = example();
+        assert_eq!(find_larger(&fs, 8033020), ["/b.txt", "/c.dat"]);
+        assert_eq!(
+            largest_files(&fs, 2),
+            [
+                (String::from("/b.txt"), 14848514),
+                (String::from("/c.dat"), 8504156)
+            ]
+        );
+    }
+
+    #[test]
+    fn deletion_plans() -> aoc22::MyResult<()> {
+        let fs = example();
+        let plan = |total_space, required_space| {
+            plan_deletion(
+                &fs,
+                &Disk {
+                    total_space,
+                    required_space,
+                },
+            )
+        };
+        assert_eq!(
+            plan(TOTAL_SPACE, REQUIRED_SPACE)?,
+            DeletionPlan {
+                dirs: vec![String::from("/d")],
+                freed: 24933642
+            }
+        );
+        // needs both directories, d is the only one big enough to go with a
+        assert_eq!(plan(48381165, 24933642 + 94853)?.dirs, ["/d", "/a"]);
+        // nothing to delete
+        assert_eq!(plan(TOTAL_SPACE, 0)?.dirs, Vec::<String>::new());
+        // even deleting every directory isn't enough
+        assert!(plan(48381165, 30000000).is_err());
+        Ok(())
+    }
+
+    /// A tree where freeing the least space takes choosing among nested directories
+    const NESTED: &str = "$ cd /\n$ ls\ndir a\ndir b\ndir c\n1 root.txt\n\
+                          $ cd a\n$ ls\ndir x\ndir y\n5 f\n$ cd x\n$ ls\n40 f\n$ cd ..\n\
+                          $ cd y\n$ ls\n30 f\ndir z\n$ cd z\n$ ls\n12 f\n$ cd /\n\
+                          $ cd b\n$ ls\n50 f\ndir w\n$ cd w\n$ ls\n25 f\n$ cd /\n\
+                          $ cd c\n$ ls\n20 f\n";
+
+    #[test]
+    fn plans_with_several_directories() -> aoc22::MyResult<()> {
+        let fs = FileSystem::from_transcript(NESTED)?;
+        let used = entry_sizes(&fs)[ROOT];
+        assert_eq!(used, 183);
+        let plan = |to_free| {
+            plan_deletion(
+                &fs,
+                &Disk {
+                    total_space: used,
+                    required_space: to_free,
+                },
+            )
+        };
+        // /a is 87 and /b 75: two directories are needed, and /a with /c frees less than /a with /b
+        assert_eq!(
+            plan(100)?,
+            DeletionPlan {
+                dirs: vec![String::from("/a"), String::from("/c")],
+                freed: 107
+            }
+        );
+        assert_eq!(plan(162)?.dirs, ["/a", "/b"]);
+        assert_eq!(plan(163)?.dirs, ["/a", "/b", "/c"]);
+        // the file in / can't be freed
+        assert_eq!(plan(182)?.freed, 182);
+        assert!(plan(183).is_err());
+        Ok(())
+    }
+
+    fn is_ancestor(fs: &FileSystem, ancestor: NodeId, mut id: NodeId) -> bool {
+        while let Some(parent) = fs.entries[id].parent {
+            if parent == ancestor {
+                return true;
+            }
+            id = parent;
+        }
+        false
+    }
+
+    /// Fewest directories, then least space, by trying every set of disjoint directories
+    fn brute_force_plan(fs: &FileSystem, to_free: usize) -> Option<(usize, usize)> {
+        let sizes = entry_sizes(fs);
+        let dirs: Vec<NodeId> = (1..fs.entries.len()).filter(|&id| fs.is_dir(id)).collect();
+        (0..1u32 << dirs.len())
+            .map(|set| {
+                let chosen: Vec<NodeId> = (0..dirs.len())
+                    .filter(|i| set & 1 << i != 0)
+                    .map(|i| dirs[i])
+                    .collect();
+                let disjoint = chosen
+                    .iter()
+                    .all(|&a| chosen.iter().all(|&b| !is_ancestor(fs, a, b)));
+                let freed = chosen.iter().map(|&id| sizes[id]).sum();
+                (disjoint, chosen.len(), freed)
+            })
+            .filter(|&(disjoint, _, freed)| disjoint && freed >= to_free)
+            .map(|(_, count, freed)| (count, freed))
+            .min()
+    }
+
+    #[test]
+    fn plans_match_brute_force() -> aoc22::MyResult<()> {
+        for transcript in [NESTED, include_str!("../inputs/example7.txt")] {
+            let fs = FileSystem::from_transcript(transcript)?;
+            let used = entry_sizes(&fs)[ROOT];
+            for to_free in (0..=used).step_by((used / 500).max(1)) {
+                let disk = Disk {
+                    total_space: used,
+                    required_space: to_free,
+                };
+                let plan = plan_deletion(&fs, &disk)
+                    .ok()
+                    .map(|plan| (plan.dirs.len(), plan.freed));
+                assert_eq!(plan, brute_force_plan(&fs, to_free), "{}", to_free);
+            }
+        }
+        Ok(())
+    }
+
+    /// Total size of the files in a real directory, as reported by the OS
+    fn disk_usage(dir: &Path) -> io::Result<usize> {
+        std::fs::read_dir(dir)?.try_fold(0, |total, entry| {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let size = if metadata.is_dir() {
+                disk_usage(&entry.path())?
+            } else {
+                metadata.len() as usize
+            };
+            Ok(total + size)
+        })
+    }
+
+    #[test]
+    fn export_stays_inside_its_directory() -> aoc22::MyResult<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().join("out");
+        for name in [
+            "..",
+            ".",
+            "",
+            "../escaped.txt",
+            "/escaped.txt",
+            "a/..",
+            "a/.",
+        ] {
+            // built by hand, as transcripts with such names are rejected
+            let mut fs = FileSystem::new();
+            let sub = fs.add(
+                ROOT,
+                "sub",
+                Node::Dir {
+                    children: BTreeMap::new(),
+                },
+            );
+            fs.add(sub, name, Node::File { size: 5 });
+            let error = export(&fs, &root).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{:?}", name);
+        }
+        assert!(!root.exists());
+        assert!(!dir.path().join("escaped.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn export_and_import_round_trip() -> aoc22::MyResult<()> {
+        let transcript = include_str!("../inputs/example7.txt");
+        let fs = FileSystem::from_transcript(transcript)?;
+        let sizes = compute_sizes(&fs);
+        let dir = tempfile::tempdir()?;
+        export(&fs, dir.path())?;
+        for (path, &size) in &sizes {
+            let real_path = dir.path().join(path.trim_start_matches('/'));
+            assert_eq!(disk_usage(&real_path)?, size, "{}", path);
+        }
+
+        let imported = import(dir.path())?;
+        assert_eq!(
+            compute_sizes(&FileSystem::from_transcript(&imported)?),
+            sizes
+        );
+        assert!(imported.starts_with("$ cd /\n$ ls\ndir a\n14848514 b.txt\n"));
+        Ok(())
+    }
+}
@@ -1,72 +1,499 @@
-use ndarray::{s, Array2};
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{self, BufWriter, Write},
+};
 
-use take_until::TakeUntilExt;
+use ndarray::Array2;
 
-aoc22::main!(day8, "../inputs/input8.txt");
+fn main() -> aoc22::MyResult<()> {
+    // the survey can be shown with `cargo run --bin day8 -- heatmap [forest]`,
+    // or saved as images with `cargo run --bin day8 -- export <visibility.pgm> <scores.ppm> [forest]`
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let read_forest = |path: Option<&String>| -> aoc22::MyResult<Survey> {
+        let input = match path {
+            Some(path) => std::fs::read_to_string(path)?,
+            None => include_str!("../inputs/input8.txt").to_string(),
+        };
+        Ok(Survey::new(parse_input(&input)?))
+    };
+    match &args[..] {
+        [command, rest @ ..] if command == "heatmap" && rest.len() <= 1 => {
+            print!("{}", read_forest(rest.first())?);
+            return Ok(());
+        }
+        [command, visibility, scores, rest @ ..] if command == "export" && rest.len() <= 1 => {
+            let survey = read_forest(rest.first())?;
+            survey.write_visibility_pgm(BufWriter::new(File::create(visibility)?))?;
+            survey.write_scores_ppm(BufWriter::new(File::create(scores)?))?;
+            return Ok(());
+        }
+        _ => (),
+    }
+    let (part1, part2) = day8(include_str!("../inputs/input8.txt"))?;
+    println!("part1: {}", part1);
+    println!("part2: {}", part2);
+    Ok(())
+}
 
 aoc22::test_with_example!(day8, "../inputs/example8.txt", 21, 8);
 
 pub fn day8(input: &str) -> aoc22::MyResult<(u32, u32)> {
     let forest = parse_input(input)?;
+    let (visible, scores) = visibility_and_scores(&forest);
 
-    let part1: u32 = forest
-        .indexed_iter()
-        .map(|(coord, _)| is_visible(coord, &forest) as u32)
-        .sum();
+    let part1 = visible.iter().filter(|&&v| v).count() as u32;
 
-    let part2: u32 = forest
-        .indexed_iter()
-        .map(|(coord, _)| scenic_score(coord, &forest))
-        .max()
-        .unwrap();
+    let part2 = *scores.iter().max().ok_or("Empty forest")?;
 
     Ok((part1, part2))
 }
 
-fn parse_input(input: &str) -> Result<Array2<u32>, ndarray::ShapeError> {
-    let lines: Vec<&str> = input.lines().collect();
-    let width = lines.len();
-    let height = lines[0].len();
+type Coord = (usize, usize);
+
+/// Visibility and scenic score of every tree, computed by looking along each row and column
+/// in both directions, in O(n) per line
+fn visibility_and_scores(forest: &Array2<u32>) -> (Array2<bool>, Array2<u32>) {
+    let (rows, cols) = forest.dim();
+    let mut visible = Array2::from_elem(forest.raw_dim(), false);
+    let mut scores = Array2::from_elem(forest.raw_dim(), 1);
+    for r in 0..rows {
+        sweep(forest, (0..cols).map(|c| (r, c)), &mut visible, &mut scores);
+        sweep(
+            forest,
+            (0..cols).rev().map(|c| (r, c)),
+            &mut visible,
+            &mut scores,
+        );
+    }
+    for c in 0..cols {
+        sweep(forest, (0..rows).map(|r| (r, c)), &mut visible, &mut scores);
+        sweep(
+            forest,
+            (0..rows).rev().map(|r| (r, c)),
+            &mut visible,
+            &mut scores,
+        );
+    }
+    (visible, scores)
+}
+
+/// Update the visibility and scores of the trees of a line with what they see looking back along it
+fn sweep(
+    forest: &Array2<u32>,
+    line: impl Iterator<Item = Coord>,
+    visible: &mut Array2<bool>,
+    scores: &mut Array2<u32>,
+) {
+    // trees that can still block the view (position in the line, height), in decreasing height
+    let mut blocking: Vec<(usize, u32)> = Vec::new();
+    for (i, coord) in line.enumerate() {
+        let height = forest[coord];
+        while blocking.last().is_some_and(|&(_, h)| h < height) {
+            blocking.pop();
+        }
+        let viewing_distance = match blocking.last() {
+            Some(&(j, _)) => i - j,
+            None => i, // up to the edge
+        };
+        visible[coord] |= blocking.is_empty();
+        scores[coord] *= viewing_distance as u32;
+        blocking.push((i, height));
+    }
+}
+
+/// Visibility and scenic scores of a whole forest, with the best treehouse location
+struct Survey {
+    forest: Array2<u32>,
+    visible: Array2<bool>,
+    scores: Array2<u32>,
+    best: Coord,
+    /// trees seen from the best location, along its four sight lines
+    sight_lines: Array2<bool>,
+}
+
+/// Size in pixels of a tree in the exported images
+const TILE_SIZE: usize = 4;
+
+const BEST_COLOUR: [u8; 3] = [255, 255, 255];
+const SIGHT_LINE_COLOUR: [u8; 3] = [0, 200, 255];
+
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+impl Survey {
+    fn new(forest: Array2<u32>) -> Survey {
+        let (visible, scores) = visibility_and_scores(&forest);
+        let (best, _) = scores
+            .indexed_iter()
+            .max_by_key(|&(_, &score)| score)
+            .expect("forests are never empty");
+        let sight_lines = sight_lines(&forest, best);
+        Survey {
+            forest,
+            visible,
+            scores,
+            best,
+            sight_lines,
+        }
+    }
+
+    /// Colour of a tree on the heat map, with the best location and its sight lines highlighted
+    fn colour(&self, coord: Coord) -> [u8; 3] {
+        if coord == self.best {
+            BEST_COLOUR
+        } else if self.sight_lines[coord] {
+            SIGHT_LINE_COLOUR
+        } else {
+            heat_colour(self.scores[coord], self.scores[self.best])
+        }
+    }
+
+    /// Write the visibility as a binary greyscale image: visible trees are white, hidden ones black
+    fn write_visibility_pgm(&self, mut out: impl Write) -> io::Result<()> {
+        let (rows, cols) = self.forest.dim();
+        write!(out, "P5\n{} {}\n255\n", cols * TILE_SIZE, rows * TILE_SIZE)?;
+        self.write_tiles(&mut out, |coord| {
+            vec![if self.visible[coord] { 255 } else { 0 }]
+        })
+    }
+
+    /// Write the scenic scores as a binary colour heat map
+    fn write_scores_ppm(&self, mut out: impl Write) -> io::Result<()> {
+        let (rows, cols) = self.forest.dim();
+        write!(out, "P6\n{} {}\n255\n", cols * TILE_SIZE, rows * TILE_SIZE)?;
+        self.write_tiles(&mut out, |coord| self.colour(coord).to_vec())
+    }
+
+    /// Write the pixels of every tree, each repeated over a square tile
+    fn write_tiles(
+        &self,
+        out: &mut impl Write,
+        pixel: impl Fn(Coord) -> Vec<u8>,
+    ) -> io::Result<()> {
+        let (rows, cols) = self.forest.dim();
+        for r in 0..rows {
+            let line: Vec<u8> = (0..cols)
+                .flat_map(|c| pixel((r, c)).repeat(TILE_SIZE))
+                .collect();
+            for _ in 0..TILE_SIZE {
+                out.write_all(&line)?;
+            }
+        }
+        out.flush()
+    }
+}
+
+/// ANSI heat map of the scenic scores, with the height of each tree, dimmed if it is hidden
+impl Display for Survey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (rows, cols) = self.forest.dim();
+        for r in 0..rows {
+            for c in 0..cols {
+                let [red, green, blue] = self.colour((r, c));
+                let dim = if self.visible[(r, c)] { "" } else { DIM };
+                // dark text on light tiles and the other way round
+                let text = if red as u32 + green as u32 + blue as u32 > 255 {
+                    30
+                } else {
+                    97
+                };
+                write!(
+                    f,
+                    "\x1b[{};48;2;{};{};{}m{}{}{}",
+                    text,
+                    red,
+                    green,
+                    blue,
+                    dim,
+                    self.forest[(r, c)],
+                    RESET
+                )?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(
+            f,
+            "best location: row {}, column {}, scenic score {}",
+            self.best.0 + 1,
+            self.best.1 + 1,
+            self.scores[self.best]
+        )
+    }
+}
+
+/// Black to red to yellow, on a log scale since a few trees have much larger scores than the rest
+fn heat_colour(score: u32, max: u32) -> [u8; 3] {
+    let t = if max == 0 {
+        0.0
+    } else {
+        (score as f64).ln_1p() / (max as f64).ln_1p()
+    };
+    let red = (t * 2.0).min(1.0);
+    let green = (t * 2.0 - 1.0).max(0.0);
+    [(red * 255.0) as u8, (green * 255.0) as u8, 0]
+}
 
-    let flat: Vec<u32> = lines
-        .into_iter()
-        .flat_map(|line| line.chars().map(|c| char::to_digit(c, 10).unwrap()))
-        .collect();
+/// Trees seen from a location, in the four directions up to and including the first blocking tree
+fn sight_lines(forest: &Array2<u32>, from: Coord) -> Array2<bool> {
+    let (rows, cols) = forest.dim();
+    let mut seen = Array2::from_elem(forest.raw_dim(), false);
+    for (dr, dc) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+        let (mut r, mut c) = from;
+        loop {
+            match (r.checked_add_signed(dr), c.checked_add_signed(dc)) {
+                (Some(next_r), Some(next_c)) if next_r < rows && next_c < cols => {
+                    (r, c) = (next_r, next_c)
+                }
+                _ => break,
+            }
+            seen[(r, c)] = true;
+            if forest[(r, c)] >= forest[from] {
+                break;
+            }
+        }
+    }
+    seen
+}
 
-    Array2::from_shape_vec((width, height), flat)
+#[derive(Debug, PartialEq)]
+enum ForestError {
+    Empty,
+    /// A row whose length differs from the first one (1-based line number)
+    RaggedRow {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// A cell that isn't a height between 0 and 9 (1-based line and column)
+    NotADigit {
+        line: usize,
+        column: usize,
+        found: char,
+    },
 }
 
-fn get_trees_in_all_directions((r, c): (usize, usize), forest: &Array2<u32>) -> [Vec<u32>; 4] {
-    let column_slice = |col, slice| forest.column(col).slice(slice).iter().copied().collect();
-    let row_slice = |row, slice| forest.row(row).slice(slice).iter().copied().collect();
-    let mut directions: [Vec<u32>; 4] = [
-        column_slice(c, s![..r]),     //up
-        column_slice(c, s![r + 1..]), //down
-        row_slice(r, s![..c]),        //left
-        row_slice(r, s![c + 1..]),    //right
-    ];
-    directions[0].reverse();
-    directions[2].reverse();
-
-    directions
+impl Display for ForestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty forest"),
+            Self::RaggedRow {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: expected {} trees, found {}",
+                line, expected, found
+            ),
+            Self::NotADigit {
+                line,
+                column,
+                found,
+            } => write!(
+                f,
+                "line {}, column {}: {:?} is not a height",
+                line, column, found
+            ),
+        }
+    }
 }
 
-fn is_visible(coord: (usize, usize), forest: &Array2<u32>) -> bool {
-    let tree_height = forest[coord];
-    get_trees_in_all_directions(coord, forest)
-        .iter()
-        .any(|direction| direction.iter().all(|&tree| tree < tree_height))
+impl Error for ForestError {}
+
+/// Parse the heights of the trees, indexed by (row, column)
+fn parse_input(input: &str) -> Result<Array2<u32>, ForestError> {
+    let lines: Vec<&str> = input.lines().collect();
+    let rows = lines.len();
+    let cols = lines.first().ok_or(ForestError::Empty)?.chars().count();
+    if cols == 0 {
+        return Err(ForestError::Empty);
+    }
+
+    let mut flat: Vec<u32> = Vec::with_capacity(rows * cols);
+    for (r, line) in lines.into_iter().enumerate() {
+        let row_start = flat.len();
+        for (c, tree) in line.chars().enumerate() {
+            let height = tree.to_digit(10).ok_or(ForestError::NotADigit {
+                line: r + 1,
+                column: c + 1,
+                found: tree,
+            })?;
+            flat.push(height);
+        }
+        if flat.len() - row_start != cols {
+            return Err(ForestError::RaggedRow {
+                line: r + 1,
+                expected: cols,
+                found: flat.len() - row_start,
+            });
+        }
+    }
+
+    Ok(Array2::from_shape_vec((rows, cols), flat).expect("every row has the same length"))
 }
 
-fn scenic_score(coord: (usize, usize), forest: &Array2<u32>) -> u32 {
-    let treehouse_height = forest[coord];
-    get_trees_in_all_directions(coord, forest)
-        .into_iter()
-        .map(|direction| {
-            direction
-                .iter()
-                .take_until(|&&tree| tree >= treehouse_height) // stop at first high tree
-                .count() as u32
+#[cfg(test)]
+mod forest_tests {
+    use super::*;
+    use ndarray::s;
+    use proptest::prelude::*;
+    use take_until::TakeUntilExt;
+
+    // Reference implementation, looking in every direction from every tree in O(n) per tree
+
+    fn get_trees_in_all_directions((r, c): Coord, forest: &Array2<u32>) -> [Vec<u32>; 4] {
+        let column_slice = |col, slice| forest.column(col).slice(slice).iter().copied().collect();
+        let row_slice = |row, slice| forest.row(row).slice(slice).iter().copied().collect();
+        let mut directions: [Vec<u32>; 4] = [
+            column_slice(c, s![..r]),     //up
+            column_slice(c, s![r + 1..]), //down
+            row_slice(r, s![..c]),        //left
+            row_slice(r, s![c + 1..]),    //right
+        ];
+        directions[0].reverse();
+        directions[2].reverse();
+
+        directions
+    }
+
+    fn is_visible(coord: Coord, forest: &Array2<u32>) -> bool {
+        let tree_height = forest[coord];
+        get_trees_in_all_directions(coord, forest)
+            .iter()
+            .any(|direction| direction.iter().all(|&tree| tree < tree_height))
+    }
+
+    fn scenic_score(coord: Coord, forest: &Array2<u32>) -> u32 {
+        let treehouse_height = forest[coord];
+        get_trees_in_all_directions(coord, forest)
+            .into_iter()
+            .map(|direction| {
+                direction
+                    .iter()
+                    .take_until(|&&tree| tree >= treehouse_height) // stop at first high tree
+                    .count() as u32
+            })
+            .product()
+    }
+
+    fn assert_matches_reference(forest: &Array2<u32>) {
+        let (visible, scores) = visibility_and_scores(forest);
+        for (coord, _) in forest.indexed_iter() {
+            assert_eq!(visible[coord], is_visible(coord, forest), "{:?}", coord);
+            assert_eq!(scores[coord], scenic_score(coord, forest), "{:?}", coord);
+        }
+    }
+
+    #[test]
+    fn input_matches_reference() -> aoc22::MyResult<()> {
+        assert_matches_reference(&parse_input(include_str!("../inputs/input8.txt"))?);
+        Ok(())
+    }
+
+    fn forests() -> impl Strategy<Value = Array2<u32>> {
+        (1usize..10, 1usize..10).prop_flat_map(|(rows, cols)| {
+            prop::collection::vec(0u32..10, rows * cols)
+                .prop_map(move |heights| Array2::from_shape_vec((rows, cols), heights).unwrap())
         })
-        .product()
+    }
+
+    proptest! {
+        #[test]
+        fn random_forests_match_reference(forest in forests()) {
+            assert_matches_reference(&forest);
+        }
+    }
+
+    #[test]
+    fn wide_and_tall_forests() -> aoc22::MyResult<()> {
+        let wide = "111111\n125321\n111111\n";
+        let forest = parse_input(wide)?;
+        assert_eq!(forest.dim(), (3, 6));
+        // the 5 sees 1 tree up and down, 2 trees on the left and 3 on the right
+        assert_eq!(day8(wide)?, (18, 6));
+
+        let tall = "111\n121\n151\n131\n121\n111\n";
+        let forest = parse_input(tall)?;
+        assert_eq!(forest.dim(), (6, 3));
+        assert_eq!(forest[(2, 1)], 5);
+        assert_eq!(day8(tall)?, (18, 6));
+
+        assert_eq!(day8("12321\n")?, (5, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_forests() {
+        assert_eq!(
+            parse_input("123\n45\n678\n"),
+            Err(ForestError::RaggedRow {
+                line: 2,
+                expected: 3,
+                found: 2
+            })
+        );
+        assert_eq!(
+            parse_input("123\n4567\n"),
+            Err(ForestError::RaggedRow {
+                line: 2,
+                expected: 3,
+                found: 4
+            })
+        );
+        assert_eq!(
+            parse_input("123\n4x6\n"),
+            Err(ForestError::NotADigit {
+                line: 2,
+                column: 2,
+                found: 'x'
+            })
+        );
+        assert_eq!(parse_input(""), Err(ForestError::Empty));
+    }
+
+    #[test]
+    fn heat_map() -> aoc22::MyResult<()> {
+        let survey = Survey::new(parse_input(include_str!("../inputs/example8.txt"))?);
+        assert_eq!(survey.best, (3, 2));
+        let mut seen: Vec<Coord> = survey
+            .sight_lines
+            .indexed_iter()
+            .filter(|&(_, &seen)| seen)
+            .map(|(coord, _)| coord)
+            .collect();
+        seen.sort();
+        assert_eq!(
+            seen,
+            vec![(1, 2), (2, 2), (3, 0), (3, 1), (3, 3), (3, 4), (4, 2)]
+        );
+
+        let mut pgm = Vec::new();
+        survey.write_visibility_pgm(&mut pgm)?;
+        let header = format!("P5\n{0} {0}\n255\n", 5 * TILE_SIZE);
+        assert!(pgm.starts_with(header.as_bytes()));
+        let pixels = &pgm[header.len()..];
+        assert_eq!(pixels.len(), 25 * TILE_SIZE * TILE_SIZE);
+        // 21 of the 25 trees are visible
+        let visible_pixels = pixels.iter().filter(|&&p| p == 255).count();
+        assert_eq!(visible_pixels, 21 * TILE_SIZE * TILE_SIZE);
+
+        let mut ppm = Vec::new();
+        survey.write_scores_ppm(&mut ppm)?;
+        let header = format!("P6\n{0} {0}\n255\n", 5 * TILE_SIZE);
+        assert!(ppm.starts_with(header.as_bytes()));
+        let pixel = |r: usize, c: usize| {
+            let start = header.len() + 3 * (r * 5 * TILE_SIZE + c);
+            &ppm[start..start + 3]
+        };
+        assert_eq!(pixel(3 * TILE_SIZE, 2 * TILE_SIZE), BEST_COLOUR);
+        assert_eq!(pixel(3 * TILE_SIZE, 0), SIGHT_LINE_COLOUR);
+        assert_eq!(pixel(0, 0), heat_colour(0, 8));
+
+        let ansi = survey.to_string();
+        assert_eq!(ansi.lines().count(), 6);
+        assert!(ansi.ends_with("best location: row 4, column 3, scenic score 8\n"));
+        Ok(())
+    }
 }
@@ -1,8 +1,55 @@
 use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
 
 use std::ops::{Add, Sub};
 
-aoc22::main!(day9, "../inputs/input9.txt");
+fn main() -> aoc22::MyResult<()> {
+    // other ropes can be simulated on the puzzle input with
+    // `cargo run --bin day9 -- <knots> [--stretch <max link>] [--track <knot>]`,
+    // the cells they visit drawn with `cargo run --bin day9 -- draw <knots> [options]`,
+    // every position of the rope with `cargo run --bin day9 -- frames <knots> [options]`,
+    // and their moves saved as images with
+    // `cargo run --bin day9 -- export <dir> <frame interval> <knots> [options]`
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let steps = parse_steps(include_str!("../inputs/input9.txt"))?;
+    match &args[..] {
+        [command, rope_args @ ..] if command == "draw" => {
+            let trace = Trace::record(&steps, RopeConfig::from_args(rope_args)?)?;
+            print!("{}", trace.draw_visited());
+            return Ok(());
+        }
+        [command, rope_args @ ..] if command == "frames" => {
+            let trace = Trace::record(&steps, RopeConfig::from_args(rope_args)?)?;
+            for step in 0..=trace.last_step() {
+                println!("{}", trace.draw_frame(step));
+            }
+            return Ok(());
+        }
+        [command, dir, every, rope_args @ ..] if command == "export" => {
+            let trace = Trace::record(&steps, RopeConfig::from_args(rope_args)?)?;
+            let dir = Path::new(dir);
+            std::fs::create_dir_all(dir)?;
+            trace.write_visited_ppm(BufWriter::new(File::create(dir.join("visited.ppm"))?))?;
+            let frames = trace.export_frames(dir, every.parse()?)?;
+            println!("{} frames written to {}", frames, dir.display());
+            return Ok(());
+        }
+        _ => (),
+    }
+    if !args.is_empty() {
+        let config = RopeConfig::from_args(&args)?;
+        println!("{}", simulate_rope(&steps, config)?.len());
+        return Ok(());
+    }
+    let (part1, part2) = day9(include_str!("../inputs/input9.txt"))?;
+    println!("part1: {}", part1);
+    println!("part2: {}", part2);
+    Ok(())
+}
 
 aoc22::test_with_example!(
     part1,
@@ -39,57 +86,327 @@ struct Step {
     count: u32,
 }
 
+#[derive(Debug, PartialEq)]
+enum StepError {
+    /// A line that isn't a direction followed by a count (1-based line number)
+    Malformed {
+        line: usize,
+    },
+    UnknownDirection {
+        line: usize,
+        direction: String,
+    },
+    BadCount {
+        line: usize,
+        count: String,
+    },
+}
+
+impl Display for StepError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed { line } => {
+                write!(f, "line {}: expected a direction and a count", line)
+            }
+            Self::UnknownDirection { line, direction } => {
+                write!(f, "line {}: unknown direction {:?}", line, direction)
+            }
+            Self::BadCount { line, count } => {
+                write!(f, "line {}: bad step count {:?}", line, count)
+            }
+        }
+    }
+}
+
+impl Error for StepError {}
+
+/// How a rope is simulated
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RopeConfig {
+    knots: usize,
+    /// how far a knot can get from the previous one along each axis before being pulled
+    max_link: u32,
+    /// the knot whose visited cells are tracked, 0 being the head
+    tracked_knot: usize,
+}
+
+impl RopeConfig {
+    /// A regular rope, tracking its tail
+    fn new(knots: usize) -> RopeConfig {
+        RopeConfig {
+            knots,
+            max_link: 1,
+            tracked_knot: knots.saturating_sub(1),
+        }
+    }
+
+    /// `<knots> [--stretch <max link>] [--track <knot>]`
+    fn from_args(args: &[String]) -> aoc22::MyResult<RopeConfig> {
+        let (knots, options) = args.split_first().ok_or("Missing knot count")?;
+        let mut config = RopeConfig::new(knots.parse()?);
+        for option in options.chunks(2) {
+            match option {
+                [flag, max_link] if flag == "--stretch" => config.max_link = max_link.parse()?,
+                [flag, knot] if flag == "--track" => config.tracked_knot = knot.parse()?,
+                _ => {
+                    return Err(format!(
+                        "Bad rope option {:?}, expected --stretch <max link> or --track <knot>",
+                        option
+                    )
+                    .into())
+                }
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> aoc22::MyResult<()> {
+        if self.knots == 0 {
+            return Err("A rope needs at least one knot".into());
+        }
+        if self.tracked_knot >= self.knots {
+            return Err(format!(
+                "Can't track knot {} of a rope with {} knots",
+                self.tracked_knot, self.knots
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
 pub fn day9(input: &str) -> aoc22::MyResult<(usize, usize)> {
     Ok((part1(input)?, part2(input)?))
 }
 
 pub fn part1(input: &str) -> aoc22::MyResult<usize> {
-    simulate_rope::<2>(input)
+    Ok(simulate_rope(&parse_steps(input)?, RopeConfig::new(2))?.len())
 }
 
 pub fn part2(input: &str) -> aoc22::MyResult<usize> {
-    simulate_rope::<10>(input)
+    Ok(simulate_rope(&parse_steps(input)?, RopeConfig::new(10))?.len())
 }
 
-fn simulate_rope<const N: usize>(input: &str) -> aoc22::MyResult<usize> {
-    let steps = parse_steps(input);
+/// Cells visited by the tracked knot of the rope
+fn simulate_rope(steps: &[Step], config: RopeConfig) -> aoc22::MyResult<HashSet<Coord>> {
+    config.validate()?;
+    Ok(rope_states(steps, config)
+        .map(|rope| rope[config.tracked_knot])
+        .collect())
+}
 
-    let start = Coord(0, 0);
-    let mut rope = vec![start; N];
+/// Positions of every knot of the rope, at the start and after each move of the head
+fn rope_states(steps: &[Step], config: RopeConfig) -> impl Iterator<Item = Vec<Coord>> + '_ {
+    let mut rope = vec![Coord(0, 0); config.knots];
+    let start = rope.clone();
+    let moves = steps
+        .iter()
+        .flat_map(|step| std::iter::repeat_n(step.offset, step.count as usize));
+    std::iter::once(start).chain(moves.map(move |offset| {
+        rope[0] = rope[0] + offset;
+        for i in 1..rope.len() {
+            rope[i] = rope_physics_step(rope[i - 1], rope[i], config.max_link);
+        }
+        rope.clone()
+    }))
+}
 
-    let mut visited = HashSet::new();
-    visited.insert(start);
+/// Every position of every knot during a simulation, to look at what the rope did
+struct Trace {
+    config: RopeConfig,
+    /// knot positions at the start and after each move of the head
+    states: Vec<Vec<Coord>>,
+}
 
-    for step in steps {
-        for _ in 0..step.count {
-            rope[0] = rope[0] + step.offset;
-            for i in 1..N {
-                rope[i] = rope_physics_step(rope[i - 1], rope[i]);
-            }
-            visited.insert(rope[N - 1]);
+const START: Coord = Coord(0, 0);
+
+const BACKGROUND_COLOUR: [u8; 3] = [0, 0, 0];
+const VISITED_COLOUR: [u8; 3] = [255, 255, 255];
+const TRAIL_COLOUR: [u8; 3] = [80, 80, 80];
+const START_COLOUR: [u8; 3] = [255, 0, 0];
+const HEAD_COLOUR: [u8; 3] = [255, 0, 0];
+const KNOT_COLOUR: [u8; 3] = [255, 200, 0];
+const TRACKED_COLOUR: [u8; 3] = [0, 255, 0];
+
+impl Trace {
+    fn record(steps: &[Step], config: RopeConfig) -> aoc22::MyResult<Trace> {
+        config.validate()?;
+        Ok(Trace {
+            config,
+            states: rope_states(steps, config).collect(),
+        })
+    }
+
+    fn last_step(&self) -> usize {
+        self.states.len() - 1
+    }
+
+    /// Cells visited by the tracked knot up to the given step
+    fn visited_until(&self, step: usize) -> HashSet<Coord> {
+        self.states[..=step]
+            .iter()
+            .map(|rope| rope[self.config.tracked_knot])
+            .collect()
+    }
+
+    /// Bottom left and top right corners of the area covered by the rope
+    fn bounds(&self) -> (Coord, Coord) {
+        self.states.iter().flatten().fold(
+            (START, START),
+            |(Coord(min_x, min_y), Coord(max_x, max_y)), &Coord(x, y)| {
+                (
+                    Coord(min_x.min(x), min_y.min(y)),
+                    Coord(max_x.max(x), max_y.max(y)),
+                )
+            },
+        )
+    }
+
+    /// Cells of the covered area, row by row from the top, as drawn in the puzzle
+    fn rows(&self) -> impl Iterator<Item = impl Iterator<Item = Coord>> {
+        let (Coord(min_x, min_y), Coord(max_x, max_y)) = self.bounds();
+        (min_y..=max_y)
+            .rev()
+            .map(move |y| (min_x..=max_x).map(move |x| Coord(x, y)))
+    }
+
+    fn draw(&self, cell: impl Fn(Coord) -> char) -> String {
+        self.rows()
+            .map(|row| {
+                row.map(&cell)
+                    .chain(std::iter::once('\n'))
+                    .collect::<String>()
+            })
+            .collect()
+    }
+
+    /// The knot drawn on a cell, the ones closer to the head hiding the others
+    fn knot_at(&self, step: usize, coord: Coord) -> Option<usize> {
+        self.states[step].iter().position(|&knot| knot == coord)
+    }
+
+    fn label(&self, knot: usize) -> char {
+        match knot {
+            0 => 'H',
+            _ if self.config.knots == 2 => 'T',
+            _ => char::from_digit(knot as u32, 10).unwrap_or('*'),
+        }
+    }
+
+    /// Cells visited by the tracked knot, marked with '#', like in the puzzle
+    fn draw_visited(&self) -> String {
+        let visited = self.visited_until(self.last_step());
+        self.draw(|coord| match coord {
+            START => 's',
+            _ if visited.contains(&coord) => '#',
+            _ => '.',
+        })
+    }
+
+    /// Positions of the knots after the given step, like in the puzzle
+    fn draw_frame(&self, step: usize) -> String {
+        self.draw(|coord| match self.knot_at(step, coord) {
+            Some(knot) => self.label(knot),
+            None if coord == START => 's',
+            None => '.',
+        })
+    }
+
+    fn write_ppm(&self, out: &mut impl Write, pixel: impl Fn(Coord) -> [u8; 3]) -> io::Result<()> {
+        let (Coord(min_x, min_y), Coord(max_x, max_y)) = self.bounds();
+        write!(
+            out,
+            "P6\n{} {}\n255\n",
+            max_x - min_x + 1,
+            max_y - min_y + 1
+        )?;
+        for row in self.rows() {
+            let line: Vec<u8> = row.flat_map(&pixel).collect();
+            out.write_all(&line)?;
         }
+        out.flush()
+    }
+
+    /// Cells visited by the tracked knot as an image
+    fn write_visited_ppm(&self, mut out: impl Write) -> io::Result<()> {
+        let visited = self.visited_until(self.last_step());
+        self.write_ppm(&mut out, |coord| match coord {
+            START => START_COLOUR,
+            _ if visited.contains(&coord) => VISITED_COLOUR,
+            _ => BACKGROUND_COLOUR,
+        })
     }
 
-    Ok(visited.len())
+    /// The rope after the given step, over the trail left by the tracked knot so far
+    fn write_frame_ppm(
+        &self,
+        step: usize,
+        trail: &HashSet<Coord>,
+        mut out: impl Write,
+    ) -> io::Result<()> {
+        self.write_ppm(&mut out, |coord| match self.knot_at(step, coord) {
+            Some(0) => HEAD_COLOUR,
+            Some(knot) if knot == self.config.tracked_knot => TRACKED_COLOUR,
+            Some(_) => KNOT_COLOUR,
+            None if trail.contains(&coord) => TRAIL_COLOUR,
+            None => BACKGROUND_COLOUR,
+        })
+    }
+
+    /// Write every `every`th step as `frame_<step>.ppm` in `dir`, and return the number of frames
+    fn export_frames(&self, dir: &Path, every: usize) -> aoc22::MyResult<usize> {
+        if every == 0 {
+            return Err("Can't export every 0th frame".into());
+        }
+        let mut trail = HashSet::new();
+        let mut frames = 0;
+        for (step, rope) in self.states.iter().enumerate() {
+            trail.insert(rope[self.config.tracked_knot]);
+            if step % every == 0 || step == self.last_step() {
+                let file = File::create(dir.join(format!("frame_{:05}.ppm", step)))?;
+                self.write_frame_ppm(step, &trail, BufWriter::new(file))?;
+                frames += 1;
+            }
+        }
+        Ok(frames)
+    }
 }
 
-fn parse_steps(input: &str) -> Vec<Step> {
-    let steps: Vec<Step> = input
+fn parse_steps(input: &str) -> Result<Vec<Step>, StepError> {
+    input
         .lines()
-        .filter_map(|l| {
-            let (direction, count) = l.split_once(' ')?;
-            let count = count.parse().unwrap();
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(n, l)| {
+            let line = n + 1;
+            let (direction, count) = l
+                .trim()
+                .split_once(' ')
+                .ok_or(StepError::Malformed { line })?;
+            let count = count.trim().parse().map_err(|_| StepError::BadCount {
+                line,
+                count: count.to_string(),
+            })?;
             let offset = match direction {
                 "U" => Coord(0, 1),
                 "D" => Coord(0, -1),
                 "L" => Coord(-1, 0),
                 "R" => Coord(1, 0),
-                _ => panic!("Bad input parsing"),
+                "UL" => Coord(-1, 1),
+                "UR" => Coord(1, 1),
+                "DL" => Coord(-1, -1),
+                "DR" => Coord(1, -1),
+                _ => {
+                    return Err(StepError::UnknownDirection {
+                        line,
+                        direction: direction.to_string(),
+                    })
+                }
             };
-            Some(Step { offset, count })
+            Ok(Step { offset, count })
         })
-        .collect();
-    steps
+        .collect()
 }
 
 fn towards(pos: i32) -> i32 {
@@ -100,13 +417,155 @@ fn towards(pos: i32) -> i32 {
     }
 }
 
-// compute the new position of the tail based on the current position of head and tail
-fn rope_physics_step(head: Coord, tail: Coord) -> Coord {
-    match head - tail {
-        Coord(2, y) => tail + Coord(1, towards(y)),
-        Coord(-2, y) => tail + Coord(-1, towards(y)),
-        Coord(x, 2) => tail + Coord(towards(x), 1),
-        Coord(x, -2) => tail + Coord(towards(x), -1),
-        _ => tail,
+// compute the new position of the tail based on the current position of head and tail:
+// as long as it is more than max_link away along an axis, it moves one cell towards the head
+fn rope_physics_step(head: Coord, mut tail: Coord, max_link: u32) -> Coord {
+    loop {
+        let Coord(x, y) = head - tail;
+        if x.unsigned_abs() <= max_link && y.unsigned_abs() <= max_link {
+            return tail;
+        }
+        tail = tail + Coord(towards(x), towards(y));
+    }
+}
+
+#[cfg(test)]
+mod rope_tests {
+    use super::*;
+
+    fn visited(input: &str, config: RopeConfig) -> aoc22::MyResult<usize> {
+        Ok(simulate_rope(&parse_steps(input)?, config)?.len())
+    }
+
+    #[test]
+    fn diagonal_moves() -> aoc22::MyResult<()> {
+        assert_eq!(visited("UR 3", RopeConfig::new(2))?, 3);
+        // going back and forth along a diagonal drags the tail along it
+        assert_eq!(visited("DL 4\nUR 8\nDR 1", RopeConfig::new(2))?, 8);
+        Ok(())
+    }
+
+    #[test]
+    fn stretchy_rope() -> aoc22::MyResult<()> {
+        let stretchy = RopeConfig {
+            max_link: 3,
+            ..RopeConfig::new(2)
+        };
+        assert_eq!(visited("R 10", stretchy)?, 8);
+        assert_eq!(visited("R 3\nU 3\nL 6", stretchy)?, 1);
+        assert_eq!(rope_physics_step(Coord(5, 1), Coord(0, 0), 2), Coord(3, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn tracked_knot() -> aoc22::MyResult<()> {
+        let example = include_str!("../inputs/example9_2.txt");
+        let tracking = |tracked_knot| RopeConfig {
+            tracked_knot,
+            ..RopeConfig::new(10)
+        };
+        assert_eq!(visited(example, tracking(9))?, 36);
+        // the first knot after the head moves like the tail of a two-knot rope
+        assert_eq!(visited(example, tracking(1))?, part1(example)?);
+        assert!(visited(example, tracking(10)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rope_options() -> aoc22::MyResult<()> {
+        let args = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+        assert_eq!(
+            RopeConfig::from_args(&args("5 --stretch 2 --track 3"))?,
+            RopeConfig {
+                knots: 5,
+                max_link: 2,
+                tracked_knot: 3
+            }
+        );
+        assert_eq!(RopeConfig::from_args(&args("5"))?, RopeConfig::new(5));
+        assert!(RopeConfig::from_args(&args("0")).is_err());
+        assert!(RopeConfig::from_args(&args("5 --track 5")).is_err());
+        assert!(RopeConfig::from_args(&args("5 --stretch")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn bad_steps() {
+        assert_eq!(
+            parse_steps("R 4\nX 2").err(),
+            Some(StepError::UnknownDirection {
+                line: 2,
+                direction: "X".to_string()
+            })
+        );
+        assert_eq!(
+            parse_steps("R 4\n\nU -1").err(),
+            Some(StepError::BadCount {
+                line: 3,
+                count: "-1".to_string()
+            })
+        );
+        assert_eq!(
+            parse_steps("R4").err(),
+            Some(StepError::Malformed { line: 1 })
+        );
+    }
+
+    #[test]
+    fn trace_drawings() -> aoc22::MyResult<()> {
+        let steps = parse_steps(include_str!("../inputs/example9_1.txt"))?;
+        let trace = Trace::record(&steps, RopeConfig::new(2))?;
+        assert_eq!(trace.last_step(), 24);
+        assert_eq!(
+            trace.draw_visited(),
+            "..##..\n...##.\n.####.\n....#.\ns###..\n"
+        );
+        assert_eq!(
+            trace.draw_frame(trace.last_step()),
+            "......\n......\n.TH...\n......\ns.....\n"
+        );
+        assert_eq!(
+            trace.draw_frame(2),
+            "......\n......\n......\n......\nsTH...\n"
+        );
+
+        let trace = Trace::record(&steps, RopeConfig::new(10))?;
+        assert_eq!(
+            trace.draw_frame(trace.last_step()),
+            "......\n......\n.1H3..\n.5....\n6.....\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn trace_images() -> aoc22::MyResult<()> {
+        let steps = parse_steps(include_str!("../inputs/example9_1.txt"))?;
+        let trace = Trace::record(&steps, RopeConfig::new(2))?;
+        let mut ppm = Vec::new();
+        trace.write_visited_ppm(&mut ppm)?;
+        let header = "P6\n6 5\n255\n";
+        assert!(ppm.starts_with(header.as_bytes()));
+        let pixels: Vec<&[u8]> = ppm[header.len()..].chunks(3).collect();
+        assert_eq!(pixels.len(), 30);
+        assert_eq!(pixels.iter().filter(|&&p| p == VISITED_COLOUR).count(), 12);
+        assert_eq!(pixels[24], START_COLOUR);
+
+        let dir = tempfile::tempdir()?;
+        assert_eq!(trace.export_frames(dir.path(), 10)?, 4);
+        let mut frames: Vec<String> = std::fs::read_dir(dir.path())?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<io::Result<_>>()?;
+        frames.sort();
+        assert_eq!(
+            frames,
+            [
+                "frame_00000.ppm",
+                "frame_00010.ppm",
+                "frame_00020.ppm",
+                "frame_00024.ppm"
+            ]
+        );
+        assert!(trace.export_frames(dir.path(), 0).is_err());
+        Ok(())
     }
 }
@@ -0,0 +1,311 @@
+use std::ops::RangeInclusive;
+
+/// Integer-like values that can be used as interval bounds
+pub trait Discrete: Copy + Ord {
+    fn checked_succ(self) -> Option<Self>;
+    fn checked_pred(self) -> Option<Self>;
+    /// Number of values in `start..=end`, saturating at usize::MAX
+    fn count_between(start: Self, end: Self) -> usize;
+}
+
+macro_rules! impl_discrete {
+    ($($t:ty),*) => {
+        $(
+            impl Discrete for $t {
+                fn checked_succ(self) -> Option<Self> {
+                    self.checked_add(1)
+                }
+                fn checked_pred(self) -> Option<Self> {
+                    self.checked_sub(1)
+                }
+                fn count_between(start: Self, end: Self) -> usize {
+                    usize::try_from(end.abs_diff(start))
+                        .ok()
+                        .and_then(|d| d.checked_add(1))
+                        .unwrap_or(usize::MAX)
+                }
+            }
+        )*
+    };
+}
+
+impl_discrete!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+/// A set of values stored as sorted, disjoint and non-adjacent inclusive intervals
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IntervalSet<T> {
+    /// (start, end) pairs, both inclusive, sorted and coalesced
+    intervals: Vec<(T, T)>,
+}
+
+impl<T> Default for IntervalSet<T> {
+    fn default() -> Self {
+        IntervalSet {
+            intervals: Vec::new(),
+        }
+    }
+}
+
+impl<T: Discrete> IntervalSet<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    /// Total number of values in the set
+    pub fn len(&self) -> usize {
+        self.intervals
+            .iter()
+            .map(|&(start, end)| T::count_between(start, end))
+            .fold(0, usize::saturating_add)
+    }
+
+    /// Iterate over the intervals of the set, in increasing order
+    pub fn iter(&self) -> impl Iterator<Item = RangeInclusive<T>> + '_ {
+        self.intervals.iter().map(|&(start, end)| start..=end)
+    }
+
+    pub fn min(&self) -> Option<T> {
+        self.intervals.first().map(|&(start, _)| start)
+    }
+
+    pub fn max(&self) -> Option<T> {
+        self.intervals.last().map(|&(_, end)| end)
+    }
+
+    /// Add every value of `range` to the set
+    pub fn insert(&mut self, range: RangeInclusive<T>) {
+        let (mut start, mut end) = range.into_inner();
+        if start > end {
+            return;
+        }
+        // first interval that ends at or after start - 1, i.e. that touches or follows the new one
+        let first = self
+            .intervals
+            .partition_point(|&(_, e)| e.checked_succ().is_some_and(|e| e < start));
+        // first interval that starts after end + 1, i.e. that can't be merged
+        let last = match end.checked_succ() {
+            Some(after_end) => self.intervals.partition_point(|&(s, _)| s <= after_end),
+            None => self.intervals.len(),
+        };
+        if first < last {
+            start = start.min(self.intervals[first].0);
+            end = end.max(self.intervals[last - 1].1);
+        }
+        self.intervals.splice(first..last, [(start, end)]);
+    }
+
+    /// Remove every value of `range` from the set
+    pub fn remove(&mut self, range: RangeInclusive<T>) {
+        let (start, end) = range.into_inner();
+        if start > end {
+            return;
+        }
+        // intervals overlapping start..=end
+        let first = self.intervals.partition_point(|&(_, e)| e < start);
+        let last = self.intervals.partition_point(|&(s, _)| s <= end);
+        if first >= last {
+            return;
+        }
+        let mut remaining = Vec::with_capacity(2);
+        let (first_start, _) = self.intervals[first];
+        let (_, last_end) = self.intervals[last - 1];
+        if first_start < start {
+            // start > first_start so it has a predecessor
+            remaining.push((first_start, start.checked_pred().unwrap()));
+        }
+        if last_end > end {
+            remaining.push((end.checked_succ().unwrap(), last_end));
+        }
+        self.intervals.splice(first..last, remaining);
+    }
+
+    pub fn contains(&self, value: T) -> bool {
+        let i = self.intervals.partition_point(|&(_, e)| e < value);
+        self.intervals.get(i).is_some_and(|&(s, _)| s <= value)
+    }
+
+    /// Whether every value of `range` is in the set
+    pub fn contains_range(&self, range: &RangeInclusive<T>) -> bool {
+        if range.is_empty() {
+            return true;
+        }
+        let i = self.intervals.partition_point(|&(_, e)| e < *range.start());
+        self.intervals
+            .get(i)
+            .is_some_and(|&(s, e)| s <= *range.start() && *range.end() <= e)
+    }
+
+    /// Whether at least one value of `range` is in the set
+    pub fn overlaps(&self, range: &RangeInclusive<T>) -> bool {
+        if range.is_empty() {
+            return false;
+        }
+        let i = self.intervals.partition_point(|&(_, e)| e < *range.start());
+        self.intervals
+            .get(i)
+            .is_some_and(|&(s, _)| s <= *range.end())
+    }
+
+    pub fn is_superset(&self, other: &Self) -> bool {
+        other.iter().all(|range| self.contains_range(&range))
+    }
+
+    pub fn is_subset(&self, other: &Self) -> bool {
+        other.is_superset(self)
+    }
+
+    /// Whether the two sets have at least one value in common
+    pub fn intersects(&self, other: &Self) -> bool {
+        other.iter().any(|range| self.overlaps(&range))
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        let mut res = self.clone();
+        other.iter().for_each(|range| res.insert(range));
+        res
+    }
+
+    pub fn intersection(&self, other: &Self) -> Self {
+        let mut intervals = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < self.intervals.len() && j < other.intervals.len() {
+            let (a_start, a_end) = self.intervals[i];
+            let (b_start, b_end) = other.intervals[j];
+            let (start, end) = (a_start.max(b_start), a_end.min(b_end));
+            if start <= end {
+                intervals.push((start, end));
+            }
+            if a_end < b_end {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        IntervalSet { intervals }
+    }
+
+    pub fn difference(&self, other: &Self) -> Self {
+        let mut res = self.clone();
+        other.iter().for_each(|range| res.remove(range));
+        res
+    }
+
+    /// Iterate over the ranges of missing values between the intervals of the set
+    pub fn gaps(&self) -> impl Iterator<Item = RangeInclusive<T>> + '_ {
+        self.intervals.windows(2).map(|pair| {
+            // intervals are non-adjacent, so these can't overflow
+            let start = pair[0].1.checked_succ().unwrap();
+            let end = pair[1].0.checked_pred().unwrap();
+            start..=end
+        })
+    }
+}
+
+impl<T: Discrete> From<RangeInclusive<T>> for IntervalSet<T> {
+    fn from(range: RangeInclusive<T>) -> Self {
+        let mut set = Self::new();
+        set.insert(range);
+        set
+    }
+}
+
+impl<T: Discrete> FromIterator<RangeInclusive<T>> for IntervalSet<T> {
+    fn from_iter<I: IntoIterator<Item = RangeInclusive<T>>>(iter: I) -> Self {
+        let mut set = Self::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<T: Discrete> Extend<RangeInclusive<T>> for IntervalSet<T> {
+    fn extend<I: IntoIterator<Item = RangeInclusive<T>>>(&mut self, iter: I) {
+        iter.into_iter().for_each(|range| self.insert(range));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::collections::BTreeSet;
+
+    fn ranges() -> impl Strategy<Value = Vec<RangeInclusive<i32>>> {
+        prop::collection::vec((-50i32..50, 0i32..15).prop_map(|(s, l)| s..=s + l), 0..8)
+    }
+
+    fn model(ranges: &[RangeInclusive<i32>]) -> BTreeSet<i32> {
+        ranges.iter().cloned().flatten().collect()
+    }
+
+    fn values(set: &IntervalSet<i32>) -> BTreeSet<i32> {
+        set.iter().flatten().collect()
+    }
+
+    fn is_normalized(set: &IntervalSet<i32>) -> bool {
+        set.intervals.iter().all(|(s, e)| s <= e)
+            && set.intervals.windows(2).all(|w| w[0].1 + 1 < w[1].0)
+    }
+
+    proptest! {
+        #[test]
+        fn insert_matches_model(a in ranges()) {
+            let set: IntervalSet<i32> = a.iter().cloned().collect();
+            prop_assert!(is_normalized(&set));
+            prop_assert_eq!(values(&set), model(&a));
+            prop_assert_eq!(set.len(), model(&a).len());
+        }
+
+        #[test]
+        fn set_operations_match_model(a in ranges(), b in ranges()) {
+            let (sa, sb): (IntervalSet<i32>, IntervalSet<i32>) =
+                (a.iter().cloned().collect(), b.iter().cloned().collect());
+            let (ma, mb) = (model(&a), model(&b));
+            for set in [sa.union(&sb), sa.intersection(&sb), sa.difference(&sb)] {
+                prop_assert!(is_normalized(&set));
+            }
+            prop_assert_eq!(values(&sa.union(&sb)), &ma | &mb);
+            prop_assert_eq!(values(&sa.intersection(&sb)), &ma & &mb);
+            prop_assert_eq!(values(&sa.difference(&sb)), &ma - &mb);
+            prop_assert_eq!(sa.is_superset(&sb), ma.is_superset(&mb));
+            prop_assert_eq!(sa.intersects(&sb), !ma.is_disjoint(&mb));
+        }
+
+        #[test]
+        fn queries_match_model(a in ranges(), start in -70i32..70, len in -2i32..20) {
+            let set: IntervalSet<i32> = a.iter().cloned().collect();
+            let m = model(&a);
+            let range = start..=start + len;
+            prop_assert_eq!(set.contains(start), m.contains(&start));
+            prop_assert_eq!(set.contains_range(&range), range.clone().all(|v| m.contains(&v)));
+            prop_assert_eq!(set.overlaps(&range), range.clone().any(|v| m.contains(&v)));
+        }
+
+        #[test]
+        fn gaps_are_missing_values(a in ranges()) {
+            let set: IntervalSet<i32> = a.iter().cloned().collect();
+            let m = model(&a);
+            let gaps: BTreeSet<i32> = set.gaps().flatten().collect();
+            let expected: BTreeSet<i32> = match (set.min(), set.max()) {
+                (Some(min), Some(max)) => (min..=max).filter(|v| !m.contains(v)).collect(),
+                _ => BTreeSet::new(),
+            };
+            prop_assert_eq!(gaps, expected);
+        }
+    }
+
+    #[test]
+    fn bounds_at_type_limits() {
+        let mut set = IntervalSet::from(0u8..=255);
+        assert_eq!(set.len(), 256);
+        set.remove(0..=0);
+        set.remove(255..=255);
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![1..=254]);
+        set.insert(255..=255);
+        set.insert(0..=0);
+        assert_eq!(set, IntervalSet::from(0..=255));
+    }
+}
@@ -1,6 +1,9 @@
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
 
+pub mod interval_set;
+pub mod ocr;
+
 pub type MyResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
 pub fn read_lines(path: &str) -> MyResult<impl Iterator<Item = String>> {
@@ -0,0 +1,178 @@
+use std::{error::Error, fmt::Display};
+
+/// Letters of the font used by the puzzles that draw text with pixels
+pub const GLYPH_WIDTH: usize = 4;
+pub const GLYPH_HEIGHT: usize = 6;
+/// Glyphs are separated by a column of unlit pixels
+const GLYPH_PITCH: usize = GLYPH_WIDTH + 1;
+
+pub const LIT: char = '#';
+pub const UNLIT: char = '.';
+
+const FONT: [(char, [&str; GLYPH_HEIGHT]); 16] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+
+const BLANK: [&str; GLYPH_HEIGHT] = ["...."; GLYPH_HEIGHT];
+
+#[derive(Debug, PartialEq)]
+pub enum OcrError {
+    /// The screen doesn't have the height of the font
+    WrongHeight { rows: usize },
+    /// A glyph that isn't in the font, starting at this column (0-based) of the screen
+    UnknownGlyph { column: usize, glyph: String },
+}
+
+impl Display for OcrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WrongHeight { rows } => write!(
+                f,
+                "expected a screen of {} rows, found {}",
+                GLYPH_HEIGHT, rows
+            ),
+            Self::UnknownGlyph { column, glyph } => {
+                write!(f, "unknown glyph at column {}:\n{}", column, glyph)
+            }
+        }
+    }
+}
+
+impl Error for OcrError {}
+
+/// Read the letters drawn on a screen of `#` and `.` pixels.
+/// Empty glyphs are read as spaces, and trailing ones are ignored.
+pub fn recognise(screen: &str) -> Result<String, OcrError> {
+    let rows: Vec<Vec<char>> = screen.lines().map(|l| l.chars().collect()).collect();
+    if rows.len() != GLYPH_HEIGHT {
+        return Err(OcrError::WrongHeight { rows: rows.len() });
+    }
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let pixel = |row: usize, column: usize| rows[row].get(column) == Some(&LIT);
+
+    let mut text = String::new();
+    for column in (0..width).step_by(GLYPH_PITCH) {
+        let glyph: [String; GLYPH_HEIGHT] = std::array::from_fn(|row| {
+            (column..column + GLYPH_WIDTH)
+                .map(|c| if pixel(row, c) { LIT } else { UNLIT })
+                .collect()
+        });
+        let separator_lit = (0..GLYPH_HEIGHT).any(|row| pixel(row, column + GLYPH_WIDTH));
+        if glyph.iter().all(|row| !row.contains(LIT)) && !separator_lit {
+            text.push(' ');
+            continue;
+        }
+        let letter = FONT
+            .iter()
+            .find(|(_, pixels)| pixels == &glyph)
+            .map(|&(letter, _)| letter)
+            .filter(|_| !separator_lit)
+            .ok_or_else(|| OcrError::UnknownGlyph {
+                column,
+                glyph: glyph.join("\n"),
+            })?;
+        text.push(letter);
+    }
+    Ok(text.trim_end().to_string())
+}
+
+/// Draw text with the font, one line per row of pixels, or None if a letter isn't in the font
+pub fn draw(text: &str) -> Option<String> {
+    let glyphs = text
+        .chars()
+        .map(|letter| match letter {
+            ' ' => Some(BLANK),
+            _ => FONT.iter().find(|(l, _)| *l == letter).map(|(_, g)| *g),
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(
+        (0..GLYPH_HEIGHT)
+            .map(|row| {
+                glyphs
+                    .iter()
+                    .map(|glyph| format!("{}{}", glyph[row], UNLIT))
+                    .collect::<String>()
+                    + "\n"
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_letter() {
+        let alphabet: String = FONT.iter().map(|&(letter, _)| letter).collect();
+        let screen = draw(&alphabet).unwrap();
+        assert_eq!(screen.lines().next().unwrap().len(), 16 * GLYPH_PITCH);
+        assert_eq!(recognise(&screen), Ok(alphabet));
+    }
+
+    #[test]
+    fn screen_of_the_puzzle() {
+        let screen = "\
+###..#.....##..####.#..#..##..####..##..
+#..#.#....#..#.#....#.#..#..#....#.#..#.
+#..#.#....#....###..##...#..#...#..#....
+###..#....#.##.#....#.#..####..#...#.##.
+#....#....#..#.#....#.#..#..#.#....#..#.
+#....####..###.#....#..#.#..#.####..###.
+";
+        assert_eq!(recognise(screen), Ok("PLGFKAZG".to_string()));
+        assert_eq!(draw("PLGFKAZG").as_deref(), Some(screen));
+    }
+
+    #[test]
+    fn spaces_and_short_rows() {
+        let screen = draw("HE  LO").unwrap();
+        assert_eq!(recognise(&screen), Ok("HE  LO".to_string()));
+        // trailing unlit pixels may be missing
+        let trimmed: String = screen
+            .lines()
+            .map(|l| l.trim_end_matches(UNLIT).to_string() + "\n")
+            .collect();
+        assert_eq!(recognise(&trimmed), Ok("HE  LO".to_string()));
+        assert_eq!(draw("hello"), None);
+    }
+
+    #[test]
+    fn errors() {
+        let mut rows: Vec<String> = draw("ABC").unwrap().lines().map(String::from).collect();
+        // fill the middle bar of the B
+        rows[2].replace_range(5..9, "####");
+        assert_eq!(
+            recognise(&rows.join("\n")),
+            Err(OcrError::UnknownGlyph {
+                column: 5,
+                glyph: "###.\n#..#\n####\n#..#\n#..#\n###.".to_string()
+            })
+        );
+        // letters touching each other
+        rows[2].replace_range(5..10, "###.#");
+        assert!(matches!(
+            recognise(&rows.join("\n")),
+            Err(OcrError::UnknownGlyph { column: 5, .. })
+        ));
+        assert_eq!(
+            recognise(&rows[..5].join("\n")),
+            Err(OcrError::WrongHeight { rows: 5 })
+        );
+    }
+}